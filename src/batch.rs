@@ -0,0 +1,153 @@
+use std::{
+    env, fs,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use serde::Serialize;
+
+use crate::ops::{Op, Plan};
+
+// one command per line, paths are relative to the current dir and words can
+// be quoted like in the shell:
+//
+// mkdir PATH
+// touch PATH
+// copy SRC... DEST
+// move SRC... DEST
+// trash PATH...
+// rename PATH NEW_NAME
+//
+// blank lines and lines starting with # are skipped
+
+// printed as a line of JSON for every command that was read
+#[derive(Serialize)]
+struct Outcome<'a> {
+    line: usize,
+    command: &'a str,
+    args: &'a [String],
+    // ok, error, planned (dry run) or skipped (after an earlier error)
+    status: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// reads commands from `input` (a file, or stdin for -), runs them and prints
+// the outcome of each, returning whether they all succeeded
+pub fn run(input: &str, dry_run: bool, keep_going: bool) -> io::Result<bool> {
+    let mut script = String::new();
+    if input == "-" {
+        io::stdin().read_to_string(&mut script)?;
+    } else {
+        script = fs::read_to_string(input)?;
+    }
+
+    let mut plan = Plan::default();
+    let mut failed = false;
+
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let words = split(line);
+        let (command, args) = match words.split_first() {
+            Some((c, a)) => (c.as_str(), a),
+            None => continue,
+        };
+
+        let result = if failed && !keep_going {
+            None
+        } else {
+            Some(parse(command, args).and_then(|op| {
+                if dry_run {
+                    op.check(&mut plan)
+                } else {
+                    op.run()
+                }
+            }))
+        };
+
+        let (status, error) = match result {
+            None => ("skipped", None),
+            Some(Ok(_)) if dry_run => ("planned", None),
+            Some(Ok(_)) => ("ok", None),
+            Some(Err(e)) => {
+                failed = true;
+                ("error", Some(e))
+            }
+        };
+        let outcome = Outcome {
+            line: i + 1,
+            command,
+            args,
+            status,
+            error,
+        };
+        println!("{}", serde_json::to_string(&outcome)?);
+    }
+
+    Ok(!failed)
+}
+
+fn parse(command: &str, args: &[String]) -> Result<Op, String> {
+    let cwd = env::current_dir().map_err(|e| e.to_string())?;
+    let paths: Vec<PathBuf> = args.iter().map(|a| cwd.join(a)).collect();
+
+    match (command, paths.len()) {
+        ("mkdir", 1) => Ok(Op::Mkdir(paths[0].clone())),
+        ("touch", 1) => Ok(Op::Touch(paths[0].clone())),
+        ("copy", n) if n >= 2 => Ok(Op::Copy(paths[..n - 1].to_vec(), paths[n - 1].clone())),
+        ("move", n) if n >= 2 => Ok(Op::Move(paths[..n - 1].to_vec(), paths[n - 1].clone())),
+        ("trash", n) if n >= 1 => Ok(Op::Trash(paths)),
+        ("rename", 2) => Ok(Op::Rename(paths[0].clone(), args[1].clone())),
+        ("mkdir" | "touch", _) => Err(format!("{} takes one path", command)),
+        ("copy" | "move", _) => Err(format!("{} takes sources and a destination", command)),
+        ("trash", _) => Err(String::from("trash takes at least one path")),
+        ("rename", _) => Err(String::from("rename takes a path and a new name")),
+        _ => Err(format!("unknown command: {}", command)),
+    }
+}
+
+// splits a line into words, honouring single quotes, double quotes and
+// backslash escapes
+fn split(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                if let Some(escaped) = chars.next() {
+                    word.push(escaped);
+                }
+                in_word = true;
+            }
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+
+    words
+}
@@ -0,0 +1,381 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+};
+
+use crate::{
+    ipc,
+    settings::{Layout, Settings, Sort},
+    state::{ChooseMode, Chooser},
+};
+
+pub const USAGE: &str = "usage: kfm [OPTIONS] [PATH]
+       kfm init bash|zsh|fish
+       kfm msg cd|select|refresh|get-selection|quit [ARG]
+       kfm batch [--dry-run] [--keep-going] [FILE]
+
+Starts in PATH, or selects it when PATH is a file.
+
+options:
+    -a, --hidden              show dot files
+    -s, --sort ORDER          name, size, time or ext
+    -c, --config FILE         read settings from FILE instead of ~/.config/kfm.toml
+        --layout LAYOUT       default or compact
+        --last-dir-file FILE  write the last dir to FILE on quit
+        --print-last-dir      print the last dir on quit
+        --choose-files FILE   pick files, writing them to FILE
+        --choose-dir FILE     pick a dir, writing it to FILE
+    -0, --print0              separate picked paths with NUL
+    -n, --dry-run             batch: check the commands without running them
+    -k, --keep-going          batch: keep going after a command fails
+    -V, --version             print the version
+    -h, --help                print this help
+
+FILE may be - to write picked paths to stdout, options override kfm.toml.
+batch reads mkdir, touch, copy, move, trash and rename commands from FILE or
+stdin and prints the outcome of each as a line of JSON.";
+
+// command line options
+#[derive(Default)]
+pub struct Args {
+    // file the cwd is written to when kfm quits
+    pub last_dir_file: Option<String>,
+    // print the cwd to stdout when kfm quits
+    pub print_last_dir: bool,
+    // shell to print the cd-on-exit wrapper for, from `kfm init <shell>`
+    pub init: Option<String>,
+    // run as a picker, see state::Chooser
+    pub chooser: Option<Chooser>,
+    // message for the kfm listening on $KFM_SOCKET, from `kfm msg ...`
+    pub msg: Option<String>,
+    // dir to start in, or file to select
+    pub path: Option<String>,
+    pub hidden: bool,
+    pub sort: Option<Sort>,
+    pub config: Option<String>,
+    pub layout: Option<Layout>,
+    pub version: bool,
+    pub help: bool,
+    // commands file for `kfm batch`, - for stdin
+    pub batch: Option<String>,
+    pub dry_run: bool,
+    pub keep_going: bool,
+}
+
+pub fn parse() -> Result<Args, String> {
+    parse_from(env::args().skip(1))
+}
+
+fn parse_from(mut raw: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut args = Args::default();
+    let mut nul = false;
+    // whether the FILE after batch was given, it defaults to stdin
+    let mut batch_file = false;
+
+    while let Some(arg) = raw.next() {
+        match arg.as_str() {
+            "--last-dir-file" => {
+                args.last_dir_file = Some(raw.next().ok_or("--last-dir-file needs a path")?);
+            }
+            "--print-last-dir" => args.print_last_dir = true,
+            "--choose-files" | "--choose-dir" => {
+                if args.chooser.is_some() {
+                    return Err("--choose-files and --choose-dir can't be combined".to_string());
+                }
+                let mode = if arg == "--choose-dir" {
+                    ChooseMode::Dir
+                } else {
+                    ChooseMode::Files
+                };
+                let output = raw.next().ok_or(format!("{} needs a file", arg))?;
+                args.chooser = Some(Chooser {
+                    mode,
+                    output,
+                    nul: false,
+                });
+            }
+            "--print0" | "-0" => nul = true,
+            "--hidden" | "-a" => args.hidden = true,
+            "--sort" | "-s" => {
+                let name = raw.next().ok_or(format!("{} needs an order", arg))?;
+                args.sort = Some(Sort::from_name(&name).ok_or(format!("unknown sort: {}", name))?);
+            }
+            "--config" | "-c" => {
+                args.config = Some(raw.next().ok_or(format!("{} needs a file", arg))?);
+            }
+            "--layout" => {
+                let name = raw.next().ok_or("--layout needs a layout")?;
+                args.layout =
+                    Some(Layout::from_name(&name).ok_or(format!("unknown layout: {}", name))?);
+            }
+            "batch" if args.batch.is_none() => args.batch = Some(String::from("-")),
+            "--dry-run" | "-n" => args.dry_run = true,
+            "--keep-going" | "-k" => args.keep_going = true,
+            _ if args.batch.is_some() && !batch_file && (arg == "-" || !arg.starts_with('-')) => {
+                args.batch = Some(arg);
+                batch_file = true;
+            }
+            "--version" | "-V" => args.version = true,
+            "--help" | "-h" => args.help = true,
+            "init" => args.init = Some(raw.next().ok_or("init needs a shell")?),
+            "msg" => {
+                let command = raw.next().ok_or("msg needs a command")?;
+                if !ipc::COMMANDS.contains(&command.as_str()) {
+                    return Err(format!("unknown msg command: {}", command));
+                }
+                let rest: Vec<String> = raw.by_ref().collect();
+                args.msg = Some(if rest.is_empty() {
+                    command
+                } else {
+                    command + " " + &rest.join(" ")
+                });
+            }
+            _ if !arg.starts_with('-') && args.path.is_none() => args.path = Some(arg),
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+
+    if let Some(chooser) = args.chooser.as_mut() {
+        chooser.nul = nul;
+    } else if nul {
+        return Err("--print0 needs --choose-files or --choose-dir".to_string());
+    }
+    if args.batch.is_none() {
+        if args.dry_run {
+            return Err("--dry-run needs batch".to_string());
+        }
+        if args.keep_going {
+            return Err("--keep-going needs batch".to_string());
+        }
+    }
+
+    Ok(args)
+}
+
+// command line options take precedence over kfm.toml
+pub fn apply(args: &Args, settings: &mut Settings) {
+    if args.hidden {
+        settings.hidden = true;
+    }
+    if let Some(sort) = args.sort {
+        settings.sort = sort;
+    }
+    if let Some(layout) = args.layout {
+        settings.layout = layout;
+    }
+}
+
+// wrapper function that runs kfm and cds the shell into its last dir
+pub fn init_script(shell: &str) -> Result<&'static str, String> {
+    match shell {
+        "bash" | "zsh" => Ok(r#"kfm() {
+    local tmp dir
+    tmp="$(mktemp)"
+    command kfm --last-dir-file "$tmp" "$@"
+    dir="$(cat "$tmp")"
+    rm -f "$tmp"
+    if [ -n "$dir" ] && [ "$dir" != "$PWD" ]; then
+        cd "$dir" || return
+    fi
+}"#),
+        "fish" => Ok(r#"function kfm
+    set -l tmp (mktemp)
+    command kfm --last-dir-file $tmp $argv
+    set -l dir (cat $tmp)
+    rm -f $tmp
+    if test -n "$dir"; and test "$dir" != "$PWD"
+        cd $dir
+    end
+end"#),
+        _ => Err(format!(
+            "unsupported shell: {} (expected bash, zsh or fish)",
+            shell
+        )),
+    }
+}
+
+// records the dir kfm quit in for the shell wrapper
+pub fn write_last_dir(args: &Args, dir: &str) -> io::Result<()> {
+    if let Some(path) = &args.last_dir_file {
+        fs::write(path, dir)?;
+    }
+    if args.print_last_dir {
+        println!("{}", dir);
+    }
+    Ok(())
+}
+
+// writes the paths picked in chooser mode, one per line or NUL terminated
+pub fn write_chosen(chooser: &Chooser, paths: &[String]) -> io::Result<()> {
+    let separator = if chooser.nul { "\0" } else { "\n" };
+    let mut out = String::new();
+    for path in paths {
+        out.push_str(path);
+        out.push_str(separator);
+    }
+
+    if chooser.output == "-" {
+        let mut stdout = io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    } else {
+        fs::write(&chooser.output, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, process::Command};
+
+    use super::*;
+    use crate::utils::TestDir;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_from(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn batch_reads_stdin_or_a_file() {
+        assert_eq!(parse(&["batch"]).unwrap().batch.as_deref(), Some("-"));
+        assert_eq!(parse(&["batch", "-"]).unwrap().batch.as_deref(), Some("-"));
+        let args = parse(&["batch", "-n", "cmds", "-k"]).unwrap();
+        assert_eq!(args.batch.as_deref(), Some("cmds"));
+        assert!(args.dry_run && args.keep_going);
+        assert!(args.path.is_none());
+    }
+
+    #[test]
+    fn batch_options_need_batch() {
+        assert!(parse(&["--dry-run"]).is_err());
+        assert!(parse(&["-k", "dir"]).is_err());
+        assert!(parse(&["--print0"]).is_err());
+    }
+
+    #[test]
+    fn last_dir_options() {
+        let args = parse(&["--last-dir-file", "/tmp/x", "--print-last-dir", "src"]).unwrap();
+        assert_eq!(args.last_dir_file.as_deref(), Some("/tmp/x"));
+        assert!(args.print_last_dir);
+        assert_eq!(args.path.as_deref(), Some("src"));
+        assert!(parse(&["--last-dir-file"]).is_err());
+    }
+
+    #[test]
+    fn writes_the_last_dir_file() {
+        let dir = TestDir::new();
+        let file = dir.join("last");
+        let args = Args {
+            last_dir_file: Some(file.to_string_lossy().into_owned()),
+            ..Args::default()
+        };
+        write_last_dir(&args, "/home/me/src").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "/home/me/src");
+        // nothing to write to without the option
+        write_last_dir(&Args::default(), "/elsewhere").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "/home/me/src");
+    }
+
+    #[test]
+    fn init_scripts_pass_the_last_dir_file() {
+        assert_eq!(init_script("bash"), init_script("zsh"));
+        for shell in ["bash", "fish"] {
+            let script = init_script(shell).unwrap();
+            assert!(script.contains("command kfm --last-dir-file"), "{}", script);
+            assert!(script.contains("cd "), "{}", script);
+        }
+        let err = init_script("tcsh").unwrap_err();
+        assert!(err.contains("unsupported shell: tcsh"), "{}", err);
+    }
+
+    #[test]
+    fn init_script_runs_in_bash() {
+        let dir = TestDir::new();
+        let target = dir.join("target");
+        fs::create_dir(&target).unwrap();
+        // a fake kfm that writes the target as its last dir
+        let kfm = dir.write(
+            "bin/kfm",
+            format!("#!/bin/sh\nprintf %s '{}' > \"$2\"\n", target.display()),
+        );
+        fs::set_permissions(&kfm, fs::Permissions::from_mode(0o700)).unwrap();
+
+        let script = format!("{}\nkfm && pwd", init_script("bash").unwrap());
+        let out = Command::new("bash")
+            .args(["-c", &script])
+            .env(
+                "PATH",
+                format!("{}:/usr/bin:/bin", dir.join("bin").display()),
+            )
+            .current_dir(&*dir)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{:?}", out);
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout).trim(),
+            target.to_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn chooser_options() {
+        let chooser = parse(&["--choose-files", "-", "-0"])
+            .unwrap()
+            .chooser
+            .unwrap();
+        assert!(chooser.mode == ChooseMode::Files && chooser.output == "-" && chooser.nul);
+        let chooser = parse(&["--choose-dir", "out"]).unwrap().chooser.unwrap();
+        assert!(chooser.mode == ChooseMode::Dir && chooser.output == "out" && !chooser.nul);
+
+        assert!(parse(&["--choose-dir", "a", "--choose-files", "b"]).is_err());
+        assert!(parse(&["--choose-files"]).is_err());
+    }
+
+    #[test]
+    fn writes_chosen_paths_by_line_or_nul() {
+        let dir = TestDir::new();
+        let output = dir.join("chosen").to_string_lossy().into_owned();
+        let paths = [String::from("/a b"), String::from("/c")];
+        let mut chooser = Chooser {
+            mode: ChooseMode::Files,
+            output: output.clone(),
+            nul: false,
+        };
+        write_chosen(&chooser, &paths).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "/a b\n/c\n");
+        chooser.nul = true;
+        write_chosen(&chooser, &paths).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "/a b\0/c\0");
+    }
+
+    #[test]
+    fn options_override_the_settings() {
+        let args = parse(&[
+            "-a", "-s", "size", "--layout", "compact", "-c", "k.toml", "dir",
+        ])
+        .unwrap();
+        assert_eq!(args.config.as_deref(), Some("k.toml"));
+        assert_eq!(args.path.as_deref(), Some("dir"));
+
+        let mut settings = Settings::default();
+        apply(&args, &mut settings);
+        assert!(settings.hidden);
+        assert!(settings.sort == Sort::Size);
+        assert!(settings.layout == Layout::Compact);
+
+        // options that aren't given leave kfm.toml alone
+        let mut settings = Settings {
+            sort: Sort::Time,
+            ..Settings::default()
+        };
+        apply(&parse(&[]).unwrap(), &mut settings);
+        assert!(!settings.hidden && settings.sort == Sort::Time);
+    }
+
+    #[test]
+    fn bad_options() {
+        assert!(parse(&["--sort", "color"]).is_err());
+        assert!(parse(&["--layout"]).is_err());
+        assert!(parse(&["a", "b"]).is_err());
+        assert!(parse(&["--nope"]).is_err());
+    }
+}
@@ -1,130 +1,905 @@
 use std::{
-    collections::VecDeque,
-    env,
-    fs::File,
-    io,
-    process::{exit, Command},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Command,
 };
 
-use crossterm::terminal::disable_raw_mode;
+use crossterm::{
+    event::{DisableMouseCapture, EnableMouseCapture},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
 
 use crate::{
-    settings,
-    state::{App, InputMode},
-    utils,
+    error::Error,
+    ipc, links,
+    ops::{self, LinkKind, Op},
+    perms::PermsEditor,
+    plugins, properties, rename,
+    settings::{OutputMode, UserCommand},
+    state::{App, ChooseMode, InputMode},
+    tmux, utils,
 };
 
-// takes a string consisting of
-// "dir args..."
-// splits on whitespace and uses any extra args after the dir
-pub fn ls(arg: &str) -> Vec<String> {
-    // use Deque to pop_front
-    let args = arg.split(" ").collect::<VecDeque<&str>>();
-    // get dir to execute ls
-    // let dir = args.pop_front().unwrap();
-    let mut res = vec![];
+// runs ls in `dir`, the string consists of
+// "args..."
+// split on whitespace
+pub fn ls(dir: &Path, arg: &str) -> io::Result<Vec<String>> {
+    let args = arg.split(" ").collect::<Vec<&str>>();
 
     // start the process
     let mut cmd = Command::new("ls");
     // add args
-    cmd.arg("-p").arg("-a").args(args); //..current_dir(dir);
+    cmd.arg("-p").arg("-a").args(args).current_dir(dir);
 
     // prepare output
-    let output = cmd.output().expect("failed to run");
-    let res_string: String = String::from_utf8(output.stdout).unwrap();
-    let lines = res_string.lines();
+    let output = cmd.output()?;
+    // 1 is for minor problems like an entry that can't be looked at, the
+    // listing is still there
+    if !output.status.success() && output.status.code() != Some(1) {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(match stderr.lines().next() {
+            Some(line) => line.to_string(),
+            None => format!("ls failed with {}", output.status),
+        }));
+    }
+    let res_string: String = String::from_utf8_lossy(&output.stdout).into_owned();
     // build result vector
-    for l in lines {
-        res.push(l.to_owned());
+    Ok(res_string.lines().map(|l| l.to_owned()).collect())
+}
+
+// enters `path`, relative paths are taken from the current dir
+pub fn enter_dir(
+    path: String,
+    app: &mut App,
+    args: &str,
+    hiding_dot_files: bool,
+) -> Result<(), Error> {
+    let path = utils::normalize(&app.cwd.join(path));
+    let dir = fs::canonicalize(&path).map_err(|e| Error::Io(path.display().to_string(), e))?;
+    // fails for files and dirs we can't list, like cd would
+    fs::read_dir(&dir).map_err(|e| Error::Io(dir.display().to_string(), e))?;
+    app.cwd = dir;
+    app.new_cwd(args, hiding_dot_files);
+    Ok(())
+}
+
+// opens the editor on `path` with the terminal handed over to it, callers
+// must clear the terminal afterwards like after resume_tui
+pub fn enter_file(path: String, editor: &str) -> Result<(), Error> {
+    enter_file_at(path, None, editor)
+}
+
+// like enter_file, starting at `line` with `editor +line path`
+pub fn enter_file_at(path: String, line: Option<usize>, editor: &str) -> Result<(), Error> {
+    let dir = Path::new(&path).parent().unwrap_or_else(|| Path::new("/"));
+    let mut cmd = Command::new(editor);
+    if let Some(line) = line {
+        cmd.arg(format!("+{}", line));
+    }
+    suspend_tui().map_err(|e| Error::Io(String::from("terminal"), e))?;
+    let status = cmd.arg(&path).current_dir(dir).status();
+    resume_tui().map_err(|e| Error::Io(String::from("terminal"), e))?;
+    status.map_err(|e| Error::Io(format!("failed to open {}", editor), e))?;
+    Ok(())
+}
+
+pub fn create_file(path: String, app: &mut App, hiding_dot_files: bool) {
+    let result = Op::Touch(PathBuf::from(&path))
+        .run()
+        .map_err(Error::Op)
+        .and_then(|_| enter_file(path, app.settings.editor()));
+    restore_input_field(app);
+    app.refresh(hiding_dot_files);
+    if let Err(e) = result {
+        app.report(e);
+    }
+}
+
+pub fn create_dir(path: String, app: &mut App, hiding_dot_files: bool) {
+    run_op(Op::Mkdir(PathBuf::from(path)), app, hiding_dot_files);
+}
+
+// runs a file operation, reporting what went wrong
+pub fn run_op(op: Op, app: &mut App, hiding_dot_files: bool) {
+    restore_input_field(app);
+    // what trashing frees is taken off the dir sizes afterwards
+    let freed: Vec<(PathBuf, Option<u64>)> = match &op {
+        Op::Trash(paths) => paths
+            .iter()
+            .map(|p| (p.clone(), app.sizes.freed_by(p)))
+            .collect(),
+        _ => vec![],
+    };
+    let result = op.run();
+
+    // marked paths that were moved or trashed no longer exist
+    if let Op::Move(..) | Op::Trash(_) = op {
+        app.marked.retain(|m| Path::new(m).exists());
+    }
+
+    // dir sizes in and above the dirs that changed are out of date
+    if let Op::Trash(_) = op {
+        let trash = ops::trash_dir().ok();
+        for (path, size) in freed {
+            forget_removed(app, &path, size, trash.as_deref());
+        }
+    } else {
+        let cwd = app.cwd.clone();
+        app.sizes.forget(&cwd);
+    }
+    if let Op::Copy(_, dest) | Op::Move(_, dest) = &op {
+        app.sizes.forget(dest);
+    }
+
+    if let Err(e) = &result {
+        app.report(Error::Op(e.clone()));
+    }
+    app.refresh(hiding_dot_files);
+
+    // the second pane shows what changed when it is in the same dir or the
+    // one copied, moved or linked into
+    let dest = match &op {
+        Op::Copy(_, dest) | Op::Move(_, dest) | Op::Link(_, dest, _) => fs::canonicalize(dest).ok(),
+        _ => None,
+    };
+    if let Some(pane) = &app.other_pane {
+        if pane.cwd == app.cwd || dest.is_some_and(|d| d == pane.cwd) {
+            app.refresh_other_pane();
+        }
+    }
+
+    // keep the renamed entry selected
+    if let (Op::Rename(_, name), Ok(_)) = (&op, result) {
+        app.select(name);
+    }
+}
+
+// absolute path of an entry in `cwd`
+pub fn absolute_path(cwd: &Path, name: &str) -> String {
+    let path = match name {
+        "./" => cwd.to_path_buf(),
+        "../" => cwd.parent().unwrap_or_else(|| Path::new("/")).to_path_buf(),
+        _ => cwd.join(name.trim_end_matches('/')),
+    };
+    path.to_string_lossy().into_owned()
+}
+
+// paths picked in chooser mode: the marked set if there is one, otherwise the
+// selection, with --choose-dir falling back to the current dir for files
+pub fn choose(app: &mut App) -> Vec<String> {
+    let mode = match &app.chooser {
+        Some(c) => c.mode,
+        None => return vec![],
+    };
+
+    let marked: Vec<String> = app
+        .marked
+        .iter()
+        .filter(|m| mode == ChooseMode::Files || Path::new(m).is_dir())
+        .map(|m| m.trim_end_matches('/').to_string())
+        .collect();
+    if !marked.is_empty() {
+        return marked;
+    }
+
+    match app.selected_name() {
+        Some(selected) if mode == ChooseMode::Files || utils::is_dir(selected) => {
+            vec![absolute_path(&app.cwd, selected)]
+        }
+        _ if mode == ChooseMode::Dir => vec![app.working_dir()],
+        _ => vec![],
+    }
+}
+
+// closes the finder on its selected path: goes to the dir holding it and
+// selects it, or with `open` enters the dir or opens the file
+pub fn finder_jump(app: &mut App, open: bool, hide: bool) -> Result<(), Error> {
+    app.input_mode = InputMode::Normal;
+    let finder = match app.finder.take() {
+        Some(finder) => finder,
+        None => return Ok(()),
+    };
+    let selected = match finder.selected() {
+        Some(selected) => selected.to_string(),
+        None => return Ok(()),
+    };
+    let path = finder.root.join(selected.trim_end_matches('/'));
+    if open && !utils::is_dir(&selected) {
+        return enter_file(path.display().to_string(), app.settings.editor());
+    }
+
+    let dir = match path.parent() {
+        Some(parent) if !open => parent.to_path_buf(),
+        _ => path.clone(),
+    };
+    enter_dir(dir.display().to_string(), app, "./", hide)?;
+    if !open {
+        if let Some(name) = path.file_name() {
+            app.select(&name.to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+// runs the grep query if it changed since the last search, otherwise opens
+// the editor at the selected match, the search and its results stay open for
+// when the editor exits
+pub fn grep_enter(app: &mut App) -> Result<(), Error> {
+    let grep = match app.grep.as_mut() {
+        Some(grep) => grep,
+        None => return Ok(()),
+    };
+    if grep.stale() {
+        return grep
+            .search()
+            // regex errors span several lines, the last one says what's wrong
+            .map_err(|e| {
+                let e = e.to_string();
+                Error::Op(format!(
+                    "invalid pattern: {}",
+                    e.lines().last().unwrap_or("")
+                ))
+            });
+    }
+    let (path, line) = match grep.selected() {
+        Some(m) => (grep.root.join(&m.path), m.line),
+        None => return Ok(()),
+    };
+    enter_file_at(
+        path.display().to_string(),
+        Some(line),
+        app.settings.editor(),
+    )
+}
+
+// opens the editor on the names of the marked entries, or of every entry in
+// the dir when nothing is marked, then lists the renames and asks before
+// doing them
+pub fn bulk_rename(app: &mut App) -> Result<(), Error> {
+    let paths: Vec<PathBuf> = if app.marked.is_empty() {
+        app.entry_names()
+            .iter()
+            .filter(|e| *e != "./" && *e != "../")
+            .map(|e| app.cwd.join(e.trim_end_matches('/')))
+            .collect()
+    } else {
+        app.marked.iter().map(PathBuf::from).collect()
+    };
+    if paths.is_empty() {
+        return Err(Error::NoSelection);
     }
 
-    return res;
+    suspend_tui().map_err(|e| Error::Io(String::from("terminal"), e))?;
+    let names = rename::edit_names(&paths, app.settings.editor());
+    resume_tui().map_err(|e| Error::Io(String::from("terminal"), e))?;
+    let renames = rename::plan(&paths, &names.map_err(Error::Op)?).map_err(Error::Op)?;
+    if renames.is_empty() {
+        return Ok(());
+    }
+
+    let name = |p: &Path| {
+        p.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    app.file_cont = renames
+        .iter()
+        .map(|(from, to)| format!("{} -> {}", name(from), name(to)))
+        .collect::<Vec<String>>()
+        .join("\n");
+    app.prev = true;
+    app.input_mode = InputMode::Renaming;
+    app.input_field_title = format!("Rename {} entries?", renames.len());
+    app.pending_renames = renames;
+    Ok(())
 }
 
-pub fn enter_dir(path: String, app: &mut App, args: &str) -> io::Result<()> {
-    return match env::set_current_dir(path) {
+// applies the renames confirmed after bulk_rename
+pub fn finish_bulk_rename(app: &mut App, hiding_dot_files: bool) {
+    let renames = std::mem::take(&mut app.pending_renames);
+    restore_input_field(app);
+    app.prev = false;
+
+    match rename::apply(&renames) {
         Ok(_) => {
-            app.new_cwd(args, true);
-            return Ok(());
+            // marks follow the renamed entries, all are taken off before any
+            // are put back so swapped names keep the right marks
+            let moved: Vec<String> = renames
+                .iter()
+                .filter_map(|(from, to)| {
+                    let from = from.to_string_lossy();
+                    app.marked.remove(from.as_ref()).then(|| {
+                        // marked dirs end with a '/'
+                        let slash = if from.ends_with('/') { "/" } else { "" };
+                        to.to_string_lossy().into_owned() + slash
+                    })
+                })
+                .collect();
+            app.marked.extend(moved);
         }
-        Err(e) => Err(e),
+        Err(e) => app.report(Error::Op(e)),
+    }
+
+    let cwd = app.cwd.clone();
+    app.sizes.forget(&cwd);
+    app.refresh(hiding_dot_files);
+}
+
+// opens the permissions editor on the selected or marked entries
+pub fn edit_perms(app: &mut App) -> Result<(), Error> {
+    let paths: Vec<PathBuf> = selected_or_marked(app)
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    if paths.is_empty() {
+        return Err(Error::NoSelection);
+    }
+    let editor = PermsEditor::open(paths).map_err(|e| Error::Io(String::from("permissions"), e))?;
+    app.perms = Some(editor);
+    app.input_mode = InputMode::Permissions;
+    Ok(())
+}
+
+// applies the permissions editor's changes, reporting every path that failed
+pub fn apply_perms(app: &mut App, hiding_dot_files: bool) {
+    let editor = match app.perms.take() {
+        Some(editor) => editor,
+        None => return,
     };
+    restore_input_field(app);
+    match editor.apply() {
+        Ok(errors) => {
+            for (path, e) in errors {
+                app.report(Error::Io(path.display().to_string(), e));
+            }
+        }
+        Err(e) => app.report(Error::Op(e)),
+    }
+    app.refresh(hiding_dot_files);
 }
 
-pub fn enter_file(path: String) -> io::Result<()> {
-    let config = settings::get_conf();
-    // default editor to vim
-    let default_editor = String::from("vim");
-    // checks for "editor" key in config and opens file in its value
-    let editor = config.get("editor").unwrap_or(&default_editor);
+// goes to the dir holding the selected symlink's target and selects the
+// target, returning whether dot files are hidden afterwards. Nothing changes
+// when it fails
+pub fn follow_link(app: &mut App, hide: bool) -> Result<bool, Error> {
+    let name = app.selected_name().ok_or(Error::NoSelection)?.to_string();
+    let target = links::resolve(&app.cwd.join(name.trim_end_matches('/')))
+        .map(|t| utils::normalize(&t))
+        .ok_or_else(|| Error::Op(format!("{} is not a symlink", name)))?;
+    if target.symlink_metadata().is_err() {
+        return Err(Error::Op(format!("{} does not exist", target.display())));
+    }
+    let dir = target.parent().unwrap_or_else(|| Path::new("/"));
+    enter_dir(dir.display().to_string(), app, "./", hide)?;
 
-    Command::new(editor)
-        .arg(path)
-        .status()
-        .expect("failed to open editor");
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut hide = hide;
+    // a dot file target needs dot files shown
+    if hide && name.starts_with('.') {
+        hide = false;
+        app.new_cwd("./", hide);
+    }
+    app.select(&name);
+    Ok(hide)
+}
 
-    disable_raw_mode()?;
-    exit(0);
+// makes links to the marked entries in the current dir
+pub fn link_marked(kind: LinkKind, app: &mut App, hiding_dot_files: bool) {
+    let sources = app
+        .marked
+        .iter()
+        .map(|m| PathBuf::from(m.trim_end_matches('/')))
+        .collect();
+    let op = Op::Link(sources, app.cwd.clone(), kind);
+    run_op(op, app, hiding_dot_files);
 }
 
-pub fn create_file(path: String) {
-    File::create(&path).expect("Failed to create file.");
-    enter_file(path).expect("Failed to enter file");
+// opens the properties popup on the selected entry, or closes it
+pub fn toggle_properties(app: &mut App) {
+    if app.properties.take().is_some() {
+        return;
+    }
+    match app.selected_name() {
+        Some(selected) => {
+            let path = absolute_path(&app.cwd, selected);
+            app.properties = Some(properties::read(Path::new(&path), &app.sizes));
+        }
+        None => app.report(Error::NoSelection),
+    }
 }
 
-pub fn create_dir(path: String, app: &mut App, hiding_dot_files: bool) {
-    Command::new("mkdir")
-        .arg("-p")
-        .arg(path)
-        .status()
-        .expect("Failed to create directory.");
-    app.new_cwd("./", hiding_dot_files);
+// default destination for copy and move, the other pane's dir in the
+// dual-pane layout
+pub fn destination_dir(app: &App) -> String {
+    match &app.other_pane {
+        Some(pane) => pane.cwd.to_string_lossy().into_owned(),
+        None => app.working_dir(),
+    }
+}
+
+// absolute paths of the marked entries, or of the selected entry when nothing
+// is marked
+pub fn selected_or_marked(app: &mut App) -> Vec<String> {
+    if !app.marked.is_empty() {
+        return app.marked.iter().cloned().collect();
+    }
+    match app.selected_name() {
+        Some(selected) if selected != "./" && selected != "../" => {
+            vec![absolute_path(&app.cwd, selected)]
+        }
+        _ => vec![],
+    }
+}
+
+// stages, unstages or discards the changes to the selected or marked entries
+pub fn git(op: fn(&str, &[String]) -> Result<(), String>, app: &mut App, hiding_dot_files: bool) {
+    let paths = selected_or_marked(app);
     restore_input_field(app);
+    if paths.is_empty() {
+        return;
+    }
+
+    if let Err(e) = op(&app.working_dir(), &paths) {
+        app.report(Error::Git(e));
+    }
+    app.refresh(hiding_dot_files);
+}
+
+// runs a command received over the socket, returning the reply; quit is
+// handled by the main loop
+pub fn remote(request: &ipc::Request, app: &mut App, hiding_dot_files: bool) -> String {
+    let arg = request.arg.as_str();
+    let result = match request.command.as_str() {
+        "cd" if !arg.is_empty() => {
+            enter_dir(arg.to_string(), app, "./", hiding_dot_files).map_err(|e| e.to_string())
+        }
+        "select" if !arg.is_empty() => {
+            let path = app.cwd.join(arg);
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let dir = path.parent().map(|p| p.to_string_lossy().into_owned());
+
+            let entered = match dir {
+                Some(dir) if dir != app.working_dir() => {
+                    enter_dir(dir, app, "./", hiding_dot_files).map_err(|e| e.to_string())
+                }
+                _ => Ok(()),
+            };
+            entered.and_then(|_| {
+                if app.select(&name) {
+                    Ok(())
+                } else {
+                    Err(format!("no entry named {}", name))
+                }
+            })
+        }
+        "refresh" => {
+            app.refresh(hiding_dot_files);
+            Ok(())
+        }
+        "get-selection" => return selected_or_marked(app).join("\n"),
+        "cd" | "select" => Err(format!("{} needs an argument", request.command)),
+        _ => Err(format!("unknown command: {}", request.command)),
+    };
+
+    match result {
+        Ok(_) => String::from("ok"),
+        Err(e) => format!("error: {}", e),
+    }
 }
 
 pub fn restore_input_field(app: &mut App) {
     app.clear_input();
+    app.pending_command = None;
+    app.pending_renames.clear();
     app.input_mode = InputMode::Normal;
 }
 
+// contents of the selected dir or file, using a plugin previewer when one is
+// configured for the file's extension. The plugin's preview replaces the
+// returned placeholder once App::poll_background gets it
+pub fn preview(selected_file: &str, app: &mut App) -> String {
+    let current_path = app.working_dir();
+    let new_path = current_path + "/" + selected_file;
+    // a slower previewer for the last file mustn't overwrite this one
+    app.preview_results = None;
+
+    if selected_file.ends_with('/') {
+        return match ls(&app.cwd, &new_path) {
+            Ok(files) => files.join("\n"),
+            Err(e) => e.to_string(),
+        };
+    }
+
+    let previewer = plugins::previewer_for(app.settings.plugins.values(), &new_path).cloned();
+    if let Some(plugin) = previewer {
+        let placeholder = format!("waiting for {}...", plugin.exec);
+        let results = plugins::spawn(plugin, plugins::RequestKind::Preview, app, |response| {
+            response.map_or_else(|e| e, |r| r.preview.unwrap_or_default())
+        });
+        app.preview_results = Some(results);
+        placeholder
+    } else {
+        prev_file(new_path)
+    }
+}
+
 pub fn prev_file(path: String) -> String {
-    let raw = Command::new("cat")
-        .arg(path)
-        .output()
-        .expect("cannot preview file");
+    match Command::new("cat").arg(path).output() {
+        Ok(raw) => String::from_utf8_lossy(&raw.stdout).to_string(),
+        Err(e) => format!("cannot preview file: {}", e),
+    }
+}
 
-    let prev = String::from_utf8_lossy(&raw.stdout);
-    prev.to_string()
+// opens the selected entry through tmux, kfm keeps running
+pub fn tmux(target: tmux::Target, app: &mut App) {
+    let path = match app.selected_name() {
+        Some(selected) => absolute_path(&app.cwd, selected),
+        None => return,
+    };
+
+    if let Err(e) = tmux::open(&target, &path, &app.settings) {
+        app.report(Error::Tmux(e));
+    }
 }
 
-pub fn tmux(path: String) -> io::Result<()> {
-    match Command::new("tmux")
-        .arg("new-window")
-        .arg("-c")
-        .arg(path.as_str())
-        .output() {
-            Err(e) => {
-                return Err(e);
-            },
-            Ok(_) => {
-                return Ok(());
-            },
-        };
+// updates the dir sizes after `path` was removed or moved under `to`, which
+// frees `size` when it is known
+fn forget_removed(app: &mut App, path: &Path, size: Option<u64>, to: Option<&Path>) {
+    match size {
+        // still there, removing it failed
+        _ if path.symlink_metadata().is_ok() => {}
+        Some(size) => app.sizes.removed(path, size, to),
+        None => app.sizes.forget(path),
+    }
 }
 
 pub fn delete(path: String, app: &mut App, hiding_dot_files: bool) {
-    if !utils::is_dir(&path) {
-        Command::new("rm")
-            .arg(path)
-            .status()
-            .expect("Failed to delete file.")
+    let removed = PathBuf::from(path.trim_end_matches('/'));
+    let freed = app.sizes.freed_by(&removed);
+    let mut cmd = Command::new("rm");
+    if utils::is_dir(&path) {
+        cmd.arg("-rf");
+    }
+    match cmd.arg(&path).output() {
+        Ok(output) if !output.status.success() => {
+            let err = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            app.report(Error::Op(err));
+        }
+        Ok(_) => {}
+        Err(e) => app.report(Error::Io(format!("failed to delete {}", path), e)),
+    }
+    forget_removed(app, &removed, freed, None);
+    app.new_cwd("./", hiding_dot_files);
+    restore_input_field(app);
+}
+
+// replaces the placeholders in a shell command with quoted paths
+// %f: selected file, %d: current dir, %s: marked files (or the selected file
+// when nothing is marked), %%: a literal %
+pub fn expand_placeholders(
+    template: &str,
+    selected: Option<&str>,
+    cwd: &str,
+    marked: &[String],
+) -> String {
+    let selected = selected.map(utils::shell_quote).unwrap_or_default();
+    let marked = if marked.is_empty() {
+        selected.clone()
     } else {
-        Command::new("rm")
-            .arg("-rf")
-            .arg(path)
-            .status()
-            .expect("Failed to delete directory.")
+        marked
+            .iter()
+            .map(|m| utils::shell_quote(m))
+            .collect::<Vec<String>>()
+            .join(" ")
     };
-    app.new_cwd("./", hiding_dot_files);
+
+    let mut res = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('f') => res.push_str(&selected),
+            Some('d') => res.push_str(&utils::shell_quote(cwd)),
+            Some('s') => res.push_str(&marked),
+            Some('%') => res.push('%'),
+            Some(other) => {
+                res.push('%');
+                res.push(other);
+            }
+            None => res.push('%'),
+        }
+    }
+
+    res
+}
+
+// expands placeholders against the current dir, selection and marked set
+pub fn expand_for_app(template: &str, app: &mut App) -> String {
+    let current_path = app.working_dir();
+    let selected = app.selected_name().map(|f| current_path.clone() + "/" + f);
+    let marked: Vec<String> = app.marked.iter().cloned().collect();
+    expand_placeholders(template, selected.as_deref(), &current_path, &marked)
+}
+
+// runs the user command with the given name, asking for confirmation first if
+// the command is configured to
+pub fn start_user_command(name: &str, app: &mut App, hiding_dot_files: bool) -> Result<(), Error> {
+    let command = match app.settings.commands.get(name) {
+        Some(c) => c.clone(),
+        None => {
+            app.report(Error::UnknownCommand(name.to_string()));
+            return Ok(());
+        }
+    };
+
+    if command.confirm {
+        app.input_mode = InputMode::Confirming;
+        app.input_field_title = format!("Run {} ({})?", name, command.run);
+        app.pending_command = Some(name.to_string());
+        Ok(())
+    } else {
+        run_user_command(&command, app, hiding_dot_files)
+    }
+}
+
+pub fn run_user_command(
+    command: &UserCommand,
+    app: &mut App,
+    hiding_dot_files: bool,
+) -> Result<(), Error> {
+    let current_path = app.working_dir();
+    let cmd = expand_for_app(&command.run, app);
     restore_input_field(app);
+
+    let res = match command.output {
+        OutputMode::Popup => {
+            app.file_cont = shell(&cmd, &current_path);
+            app.prev = true;
+            Ok(())
+        }
+        OutputMode::Interactive => shell_interactive(&cmd, &current_path),
+        OutputMode::Silent => {
+            shell(&cmd, &current_path);
+            Ok(())
+        }
+    };
+    // the command may have changed the dir even if it failed
+    app.new_cwd("./", hiding_dot_files);
+
+    res
+}
+
+// runs a command through `sh -c` and returns its stdout and stderr
+pub fn shell(cmd: &str, cwd: &str) -> String {
+    match Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .current_dir(cwd)
+        .output()
+    {
+        Ok(output) => {
+            let mut res = String::from_utf8_lossy(&output.stdout).to_string();
+            res.push_str(&String::from_utf8_lossy(&output.stderr));
+            if !output.status.success() {
+                res.push_str(&format!("\n[{}]", output.status));
+            }
+            res
+        }
+        Err(e) => format!("failed to run command: {}", e),
+    }
+}
+
+// runs a command through `sh -c` with the terminal handed back to it,
+// waiting for ENTER before returning to kfm
+pub fn shell_interactive(cmd: &str, cwd: &str) -> Result<(), Error> {
+    suspend_tui().map_err(|e| Error::Io(String::from("terminal"), e))?;
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .current_dir(cwd)
+        .status();
+    match status {
+        Ok(s) if !s.success() => println!("\n[{}]", s),
+        Ok(_) => {}
+        Err(e) => println!("failed to run command: {}", e),
+    }
+    print!("Press ENTER to continue");
+    let mut buf = String::new();
+    let waited = io::stdout()
+        .flush()
+        .and_then(|_| io::stdin().read_line(&mut buf));
+
+    // the terminal has to come back even if waiting failed
+    resume_tui()
+        .and(waited.map(|_| ()))
+        .map_err(|e| Error::Io(String::from("terminal"), e))
+}
+
+// hands the terminal back to the shell
+pub fn suspend_tui() -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)
+}
+
+// takes the terminal back after suspend_tui, callers must clear the terminal
+// so the next draw repaints everything
+pub fn resume_tui() -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_quoted() {
+        let marked = [String::from("/d/a b"), String::from("/d/it's")];
+        assert_eq!(
+            expand_placeholders("cp %s %d", Some("/d/x"), "/d", &marked),
+            "cp '/d/a b' '/d/it'\\''s' '/d'"
+        );
+        assert_eq!(
+            expand_placeholders("wc %f", Some("/d/x"), "/d", &[]),
+            "wc '/d/x'"
+        );
+    }
+
+    #[test]
+    fn selection_stands_in_for_no_marks() {
+        assert_eq!(
+            expand_placeholders("rm %s", Some("/d/x"), "/d", &[]),
+            "rm '/d/x'"
+        );
+        assert_eq!(expand_placeholders("ls %f", None, "/d", &[]), "ls ");
+    }
+
+    #[test]
+    fn percent_signs() {
+        assert_eq!(
+            expand_placeholders("date +%%s %x %", None, "/d", &[]),
+            "date +%s %x %"
+        );
+    }
+
+    #[test]
+    fn ls_fails_for_missing_dirs() {
+        let dir = utils::TestDir::new();
+        dir.write("a", "");
+        assert!(ls(&dir, "./").unwrap().contains(&String::from("a")));
+        let err = ls(&dir, "nope").unwrap_err();
+        assert!(err.to_string().contains("nope"), "{}", err);
+    }
+
+    #[test]
+    fn errors_are_reported_instead_of_returned() {
+        let dir = utils::TestDir::new();
+        let mut app = App::new(crate::settings::Settings::default(), dir.to_path_buf());
+
+        start_user_command("nope", &mut app, true).unwrap();
+        assert_eq!(app.errors, ["no command named 'nope'"]);
+        assert_eq!(app.status_message(), Some("no command named 'nope'"));
+
+        let err = enter_dir(String::from("nope"), &mut app, "./", true).unwrap_err();
+        assert!(matches!(err, Error::Io(..)));
+        assert_eq!(app.cwd, dir.to_path_buf());
+
+        // the listing of a dir that went away falls back to its parent
+        fs::create_dir(dir.join("gone")).unwrap();
+        enter_dir(String::from("gone"), &mut app, "./", true).unwrap();
+        fs::remove_dir(dir.join("gone")).unwrap();
+        app.new_cwd("./", true);
+        assert_eq!(app.cwd, dir.to_path_buf());
+        assert_eq!(app.errors.len(), 1);
+    }
+
+    #[test]
+    fn remote_cd_keeps_dot_files_shown() {
+        let dir = utils::TestDir::new();
+        dir.write("sub/.hidden", "");
+        let mut app = App::new(crate::settings::Settings::default(), dir.to_path_buf());
+        let (reply, _) = std::sync::mpsc::channel();
+        let request = ipc::Request {
+            command: String::from("cd"),
+            arg: dir.join("sub").to_string_lossy().into_owned(),
+            reply,
+        };
+
+        assert_eq!(remote(&request, &mut app, false), "ok");
+        assert!(app.select(".hidden"));
+        assert_eq!(remote(&request, &mut app, true), "ok");
+        assert!(!app.select(".hidden"));
+    }
+
+    #[test]
+    fn long_entries_are_used_by_file_name() {
+        let dir = utils::TestDir::new();
+        dir.write("a b", "");
+        dir.write("sub/c", "");
+        let mut app = App::new(crate::settings::Settings::default(), dir.to_path_buf());
+        app.new_cwd("-l", true);
+
+        let mut names = app.entry_names();
+        names.sort();
+        assert_eq!(names, ["../", "./", "a b", "sub/"]);
+        assert!(app.select("a b"));
+        assert_eq!(
+            selected_or_marked(&mut app),
+            [dir.join("a b").to_string_lossy()]
+        );
+        assert!(app.select("sub"));
+        let selected = app.selected_name().unwrap().to_string();
+        enter_dir(selected, &mut app, "-l", true).unwrap();
+        assert_eq!(app.cwd, dir.join("sub"));
+    }
+
+    #[test]
+    fn choose_takes_the_marks_or_the_selection() {
+        let dir = utils::TestDir::new();
+        dir.write("a", "");
+        dir.write("sub/b", "");
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let mut app = App::new(crate::settings::Settings::default(), dir.to_path_buf());
+        app.chooser = Some(crate::state::Chooser {
+            mode: ChooseMode::Files,
+            output: String::from("-"),
+            nul: false,
+        });
+
+        assert!(app.select("a"));
+        assert_eq!(choose(&mut app), [path("a")]);
+        app.toggle_mark(path("sub/"));
+        app.toggle_mark(path("sub/b"));
+        assert_eq!(choose(&mut app), [path("sub"), path("sub/b")]);
+
+        // only dirs count for --choose-dir, with the cwd standing in for files
+        app.chooser.as_mut().unwrap().mode = ChooseMode::Dir;
+        assert_eq!(choose(&mut app), [path("sub")]);
+        app.marked.clear();
+        assert_eq!(choose(&mut app), [app.working_dir()]);
+        assert!(app.select("sub"));
+        assert_eq!(choose(&mut app), [path("sub")]);
+    }
+
+    #[test]
+    fn commands_run_in_the_tracked_dir() {
+        let dir = utils::TestDir::new();
+        dir.write("sub/a", "");
+        let mut app = App::new(crate::settings::Settings::default(), dir.to_path_buf());
+        let process_cwd = std::env::current_dir().unwrap();
+
+        enter_dir(String::from("sub"), &mut app, "./", true).unwrap();
+        assert_eq!(app.cwd, dir.join("sub"));
+        assert_eq!(std::env::current_dir().unwrap(), process_cwd);
+        assert!(app.select("a"));
+
+        let command = |run: &str, output| UserCommand {
+            run: run.to_string(),
+            key: None,
+            confirm: false,
+            output,
+        };
+        run_user_command(&command("pwd", OutputMode::Popup), &mut app, true).unwrap();
+        assert_eq!(app.file_cont.trim(), dir.join("sub").to_str().unwrap());
+        run_user_command(&command("touch b", OutputMode::Silent), &mut app, true).unwrap();
+        assert!(dir.join("sub/b").exists());
+        assert!(app.select("b"));
+
+        enter_dir(String::from("../"), &mut app, "./", true).unwrap();
+        assert_eq!(app.cwd, dir.to_path_buf());
+        assert_eq!(std::env::current_dir().unwrap(), process_cwd);
+    }
 }
@@ -0,0 +1,32 @@
+use std::{fmt, io};
+
+// everything that can go wrong while running a command from the tui, shown
+// in the status line and kept in the error log
+#[derive(Debug)]
+pub enum Error {
+    // an io error, with the path or action it happened on
+    Io(String, io::Error),
+    // a file operation was refused or failed
+    Op(String),
+    Git(String),
+    Plugin(String),
+    Tmux(String),
+    // the command needs an entry and nothing is selected
+    NoSelection,
+    // a user command or plugin with this name isn't configured
+    UnknownCommand(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(context, e) => write!(f, "{}: {}", context, e),
+            Error::Op(e) => write!(f, "{}", e),
+            Error::Git(e) => write!(f, "git: {}", e),
+            Error::Plugin(e) => write!(f, "plugin: {}", e),
+            Error::Tmux(e) => write!(f, "tmux: {}", e),
+            Error::NoSelection => write!(f, "no file/directory currently selected"),
+            Error::UnknownCommand(name) => write!(f, "no command named '{}'", name),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use ignore::WalkBuilder;
+use tui::widgets::ListState;
+
+// paths are sent to the finder in batches this big
+const BATCH: usize = 512;
+
+// matches listed at once, the rest are counted but not drawn
+const SHOWN: usize = 1000;
+
+// bytes of a file shown in the preview
+const PREVIEW_BYTES: u64 = 16 * 1024;
+
+// fuzzy finder over every path under a dir, walked on a background thread
+pub struct Finder {
+    pub root: PathBuf,
+    pub query: String,
+    // paths relative to root, dirs end with a '/'
+    paths: Vec<String>,
+    // indices into paths of the ones matching the query, best first
+    matches: Vec<usize>,
+    pub state: ListState,
+    // still walking while this is Some
+    walker: Option<Receiver<Vec<String>>>,
+    // selected path and its preview
+    preview: Option<(String, String)>,
+}
+
+impl Finder {
+    // starts walking `root`, skipping what .gitignore files exclude and dot
+    // files when `hide` is set
+    pub fn start(root: PathBuf, hide: bool) -> Finder {
+        let (tx, rx) = mpsc::channel();
+        let walk = WalkBuilder::new(&root)
+            .hidden(hide)
+            .require_git(false)
+            .build();
+        let base = root.clone();
+
+        thread::spawn(move || {
+            let mut batch = vec![];
+            for entry in walk.flatten() {
+                let path = match entry.path().strip_prefix(&base) {
+                    Ok(p) if !p.as_os_str().is_empty() => p.to_string_lossy().into_owned(),
+                    _ => continue,
+                };
+                let dir = entry.file_type().is_some_and(|t| t.is_dir());
+                batch.push(if dir { path + "/" } else { path });
+                if batch.len() == BATCH && tx.send(std::mem::take(&mut batch)).is_err() {
+                    // the finder was closed
+                    return;
+                }
+            }
+            let _ = tx.send(batch);
+        });
+
+        let mut state = ListState::default();
+        state.select(Some(0));
+        Finder {
+            root,
+            query: String::new(),
+            paths: vec![],
+            matches: vec![],
+            state,
+            walker: Some(rx),
+            preview: None,
+        }
+    }
+
+    // takes in paths found since the last call, returns whether there were any
+    pub fn poll(&mut self) -> bool {
+        let rx = match &self.walker {
+            Some(rx) => rx,
+            None => return false,
+        };
+        let mut found = vec![];
+        loop {
+            match rx.try_recv() {
+                Ok(batch) => found.extend(batch),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.walker = None;
+                    break;
+                }
+            }
+        }
+        if found.is_empty() {
+            return self.walker.is_none();
+        }
+        let start = self.paths.len();
+        self.paths.extend(found);
+        self.rematch(start);
+        true
+    }
+
+    pub fn walking(&self) -> bool {
+        self.walker.is_some()
+    }
+
+    pub fn set_query(&mut self, query: String) {
+        // typing more only drops matches, so only the current ones need
+        // checking
+        let narrowing = query.starts_with(&self.query);
+        self.query = query;
+        if narrowing {
+            self.rematch(self.paths.len());
+        } else {
+            self.matches.clear();
+            self.rematch(0);
+        }
+        self.state.select(Some(0));
+    }
+
+    // matches the paths from `start` on against the query, keeping the
+    // matches ordered by score
+    fn rematch(&mut self, start: usize) {
+        let mut scored: Vec<(i64, usize)> = self
+            .matches
+            .iter()
+            .filter_map(|&i| score(&self.query, &self.paths[i]).map(|s| (s, i)))
+            .collect();
+        scored.extend(
+            (start..self.paths.len())
+                .filter_map(|i| score(&self.query, &self.paths[i]).map(|s| (s, i))),
+        );
+        scored.sort_by_key(|&(s, i)| (-s, i));
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+    }
+
+    // the best matches, as many as are listed
+    pub fn matches(&self) -> Vec<&str> {
+        self.matches
+            .iter()
+            .take(SHOWN)
+            .map(|&i| self.paths[i].as_str())
+            .collect()
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn total(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        let i = self.state.selected()?;
+        self.matches.get(i).map(|&m| self.paths[m].as_str())
+    }
+
+    // moves the selection by `delta`, stopping at either end
+    pub fn scroll(&mut self, delta: isize) {
+        let shown = self.matches.len().min(SHOWN);
+        if shown == 0 {
+            return;
+        }
+        let i = self.state.selected().unwrap_or(0) as isize + delta;
+        self.state
+            .select(Some(i.clamp(0, shown as isize - 1) as usize));
+    }
+
+    // contents of the selected file or dir, read again only when the
+    // selection changes
+    pub fn preview(&mut self) -> &str {
+        let selected = self.selected().unwrap_or_default().to_string();
+        if self.preview.as_ref().map(|(p, _)| p) != Some(&selected) {
+            let content = if selected.is_empty() {
+                String::new()
+            } else {
+                preview(&self.root.join(&selected))
+            };
+            self.preview = Some((selected, content));
+        }
+        self.preview.as_ref().map(|(_, c)| c.as_str()).unwrap_or("")
+    }
+}
+
+fn preview(path: &Path) -> String {
+    if path.is_dir() {
+        let mut names: Vec<String> = match fs::read_dir(path) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect(),
+            Err(e) => return e.to_string(),
+        };
+        names.sort();
+        return names.join("\n");
+    }
+
+    let mut buf = vec![];
+    match fs::File::open(path).and_then(|f| f.take(PREVIEW_BYTES).read_to_end(&mut buf)) {
+        Ok(_) => String::from_utf8_lossy(&buf).into_owned(),
+        Err(e) => e.to_string(),
+    }
+}
+
+// how well `candidate` matches `query`, None when the query's characters
+// don't all appear in order; matches at the start of a path component and
+// runs of consecutive characters score higher, shorter paths break ties.
+// Matching ignores case unless the query has an uppercase letter
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let case_sensitive = query.chars().any(|c| c.is_uppercase());
+    let fold = |c: char| {
+        if case_sensitive {
+            c
+        } else {
+            c.to_ascii_lowercase()
+        }
+    };
+
+    let mut query = query.chars().map(fold).peekable();
+    let mut score = 0;
+    let mut previous: Option<char> = None;
+    let mut consecutive = false;
+
+    for c in candidate.chars() {
+        let matched = query.peek() == Some(&fold(c));
+        if matched {
+            query.next();
+            score += 1;
+            if consecutive {
+                score += 4;
+            }
+            if matches!(previous, None | Some('/' | '_' | '-' | '.' | ' ')) {
+                score += 8;
+            }
+        }
+        consecutive = matched;
+        previous = Some(c);
+    }
+
+    if query.peek().is_some() {
+        return None;
+    }
+    Some(score * 100 - candidate.len() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn characters_must_appear_in_order() {
+        assert!(score("mn", "src/main.rs").is_some());
+        assert!(score("nm", "src/main.rs").is_none());
+        assert!(score("", "anything").is_some());
+    }
+
+    #[test]
+    fn case_is_ignored_unless_the_query_has_uppercase() {
+        assert!(score("readme", "README.md").is_some());
+        assert!(score("Readme", "README.md").is_none());
+        assert!(score("README", "README.md").is_some());
+    }
+
+    #[test]
+    fn component_starts_and_runs_score_higher() {
+        // "m" at the start of "main" beats one in the middle of a word
+        assert!(score("m", "src/main.rs") > score("m", "src/lemon.rs"));
+        // and so do characters right after each other
+        assert!(score("ai", "bain") > score("ai", "baxi"));
+        // shorter paths break ties
+        assert!(score("a", "a") > score("a", "a/b"));
+    }
+}
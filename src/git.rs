@@ -0,0 +1,398 @@
+use std::{collections::HashMap, process::Command};
+
+use crate::utils;
+
+// git state of a listing entry, dirs combine the state of everything in them
+#[derive(Default, Clone, Copy)]
+pub struct Status {
+    pub staged: bool,
+    pub modified: bool,
+    pub untracked: bool,
+    pub ignored: bool,
+    pub conflicted: bool,
+}
+
+impl Status {
+    fn merge(&mut self, other: Status) {
+        self.staged |= other.staged;
+        self.modified |= other.modified;
+        self.untracked |= other.untracked;
+        self.conflicted |= other.conflicted;
+    }
+
+    pub fn is_clean(&self) -> bool {
+        !(self.staged || self.modified || self.untracked || self.ignored || self.conflicted)
+    }
+}
+
+// git state of the current dir
+#[derive(Clone)]
+pub struct GitStatus {
+    // None when HEAD is detached
+    pub branch: Option<String>,
+    // file name of the listing entry -> status, clean entries are left out
+    pub entries: HashMap<String, Status>,
+}
+
+// runs git in `cwd`, returning None when it isn't inside a work tree
+fn git(cwd: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(cwd)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// reads the index and work tree under `cwd` and works out the status of each
+// entry in the listing
+pub fn status(cwd: &str, entries: &[String]) -> Option<GitStatus> {
+    // where `cwd` is in the repo, "" at its root and "src/" under it
+    let prefix = git(cwd, &["rev-parse", "--show-prefix"])?;
+    let raw = git(
+        cwd,
+        &[
+            "status",
+            "--porcelain",
+            "-z",
+            "--branch",
+            "--ignored=matching",
+            "--",
+            ".",
+        ],
+    )?;
+
+    let (branch, found) = parse_status(&raw, prefix.trim_end());
+
+    let statuses = entries
+        .iter()
+        .map(|entry| utils::entry_name(entry))
+        .filter_map(|name| Some((name.to_string(), *found.get(name)?)))
+        .filter(|(_, status)| !status.is_clean())
+        .collect();
+
+    Some(GitStatus {
+        branch,
+        entries: statuses,
+    })
+}
+
+// splits `git status --porcelain -z --branch` output into the branch and the
+// status of each listing entry, `prefix` being where the listing is in the repo
+fn parse_status(raw: &str, prefix: &str) -> (Option<String>, HashMap<String, Status>) {
+    let mut branch = None;
+    // listing entry name -> status of everything under it
+    let mut found: HashMap<String, Status> = HashMap::new();
+
+    let mut records = raw.split('\0');
+    while let Some(record) = records.next() {
+        if let Some(header) = record.strip_prefix("## ") {
+            branch = parse_branch(header);
+            continue;
+        }
+        if record.len() < 4 {
+            continue;
+        }
+
+        let (code, path) = record.split_at(3);
+        let mut code = code.chars();
+        let x = code.next().unwrap_or(' ');
+        let y = code.next().unwrap_or(' ');
+
+        // renames and copies are followed by the original path
+        if x == 'R' || x == 'C' {
+            records.next();
+        }
+
+        let status = match (x, y) {
+            ('?', '?') => Status {
+                untracked: true,
+                ..Status::default()
+            },
+            ('!', '!') => Status {
+                ignored: true,
+                ..Status::default()
+            },
+            ('U', _) | (_, 'U') | ('A', 'A') | ('D', 'D') => Status {
+                conflicted: true,
+                ..Status::default()
+            },
+            _ => Status {
+                staged: x != ' ',
+                modified: y != ' ',
+                ..Status::default()
+            },
+        };
+        // paths are relative to the root of the repo, dirs end with a '/'
+        let path = match path.strip_prefix(prefix) {
+            Some(path) => path,
+            None => continue,
+        };
+        let (entry, itself) = match path.split_once('/') {
+            None => (path.to_string(), true),
+            Some((dir, inside)) => (dir.to_string() + "/", inside.is_empty()),
+        };
+        let entry_status = found.entry(entry).or_default();
+        entry_status.merge(status);
+        // ignored only applies to the entry itself since git doesn't report
+        // ignored files inside ignored dirs
+        entry_status.ignored |= itself && status.ignored;
+    }
+
+    (branch, found)
+}
+
+// "main...origin/main [ahead 1]", "No commits yet on main" or "HEAD (no branch)"
+fn parse_branch(header: &str) -> Option<String> {
+    if header.starts_with("HEAD (no branch)") {
+        return None;
+    }
+    let header = header
+        .strip_prefix("No commits yet on ")
+        .or_else(|| header.strip_prefix("Initial commit on "))
+        .unwrap_or(header);
+    let branch = header.split("...").next()?.split(' ').next()?;
+    Some(branch.to_string())
+}
+
+// runs git in `cwd` for its side effects, returning its error output on failure
+fn run(cwd: &str, args: &[&str], paths: &[String]) -> Result<(), String> {
+    let output = Command::new("git")
+        .args(args)
+        .arg("--")
+        .args(paths)
+        .current_dir(cwd)
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+pub fn stage(cwd: &str, paths: &[String]) -> Result<(), String> {
+    run(cwd, &["add"], paths)
+}
+
+pub fn unstage(cwd: &str, paths: &[String]) -> Result<(), String> {
+    run(cwd, &["restore", "--staged"], paths)
+}
+
+// throws away work tree changes to tracked files, untracked files are left alone
+pub fn discard(cwd: &str, paths: &[String]) -> Result<(), String> {
+    run(cwd, &["restore"], paths)
+}
+
+// staged and unstaged changes to `path` against HEAD, untracked files are
+// shown as entirely new
+pub fn diff(cwd: &str, path: &str) -> String {
+    let tracked = git(cwd, &["ls-files", "--error-unmatch", "--", path]).is_some();
+    let args = if tracked {
+        vec!["diff", "--no-color", "HEAD", "--", path]
+    } else {
+        vec!["diff", "--no-color", "--no-index", "--", "/dev/null", path]
+    };
+
+    // `git diff --no-index` exits with 1 when there are differences
+    match Command::new("git").args(&args).current_dir(cwd).output() {
+        Ok(output) if output.stdout.is_empty() => {
+            let err = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            if err.is_empty() {
+                String::from("No changes")
+            } else {
+                err
+            }
+        }
+        Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Err(e) => format!("failed to run git: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn branch_names() {
+        assert_eq!(
+            parse_branch("main...origin/main [ahead 1]").as_deref(),
+            Some("main")
+        );
+        assert_eq!(
+            parse_branch("No commits yet on dev").as_deref(),
+            Some("dev")
+        );
+        assert_eq!(parse_branch("HEAD (no branch)"), None);
+    }
+
+    #[test]
+    fn entries_get_the_status_of_what_is_under_them() {
+        let dir = utils::TestDir::new();
+        let cwd = dir.to_string_lossy().into_owned();
+        let run = |args: &[&str]| git(&cwd, args).unwrap();
+        run(&["init", "-q", "-b", "main"]);
+        for file in ["tracked", "staged", "src/deep/tracked", "build/out"] {
+            dir.write(file, "a");
+        }
+        dir.write(".gitignore", "build/\n");
+        run(&["add", "."]);
+        run(&[
+            "-c",
+            "user.name=t",
+            "-c",
+            "user.email=t@t",
+            "commit",
+            "-qm",
+            "init",
+        ]);
+        dir.write("tracked", "b");
+        dir.write("staged", "b");
+        run(&["add", "staged"]);
+        dir.write("src/deep/tracked", "b");
+        dir.write("src/new", "a");
+
+        let entries = names(&[
+            "../",
+            "./",
+            "build/",
+            "src/",
+            ".gitignore",
+            "staged",
+            "tracked",
+        ]);
+        let git_status = status(&cwd, &entries).unwrap();
+        assert_eq!(git_status.branch.as_deref(), Some("main"));
+        assert!(git_status.entries["tracked"].modified);
+        assert!(git_status.entries["staged"].staged);
+        assert!(git_status.entries["build/"].ignored);
+        let src = git_status.entries["src/"];
+        assert!(src.modified && src.untracked && !src.ignored);
+        assert_eq!(git_status.entries.len(), 4);
+
+        // from a subdir only what is under it is looked at
+        let entries = names(&["../", "./", "deep/", "new"]);
+        let git_status = status(&(cwd.clone() + "/src"), &entries).unwrap();
+        assert!(git_status.entries["deep/"].modified);
+        assert!(git_status.entries["new"].untracked);
+        assert_eq!(git_status.entries.len(), 2);
+
+        // long listings are looked up by file name
+        let entries = names(&[
+            "drwxr-xr-x 3 u g 4096 Oct 19 08:31 deep/",
+            "-rw-r--r-- 1 u g    1 Oct 19 08:31 new",
+        ]);
+        let git_status = status(&(cwd.clone() + "/src"), &entries).unwrap();
+        assert!(git_status.entries["deep/"].modified);
+        assert!(git_status.entries["new"].untracked);
+    }
+
+    #[test]
+    fn staged_and_unstaged_changes() {
+        let raw = [
+            "## main...origin/main",
+            "M  staged",
+            " M modified",
+            "MM both",
+            "A  added",
+            " D gone",
+            "R  renamed",
+            "old name",
+            "UU conflict",
+            "AA added-twice",
+            "?? new",
+            "!! build/",
+            "MM src/a",
+            "?? src/b",
+            "",
+        ]
+        .join("\0");
+        let (branch, found) = parse_status(&raw, "");
+        assert_eq!(branch.as_deref(), Some("main"));
+
+        let is = |name: &str, staged, modified| {
+            let status = found[name];
+            assert_eq!(
+                (status.staged, status.modified),
+                (staged, modified),
+                "{}",
+                name
+            );
+            assert!(!status.conflicted && !status.untracked, "{}", name);
+        };
+        is("staged", true, false);
+        is("modified", false, true);
+        is("both", true, true);
+        is("added", true, false);
+        is("gone", false, true);
+        is("renamed", true, false);
+        // the original name of a rename isn't an entry of its own
+        assert!(!found.contains_key("old name"));
+        assert!(found["conflict"].conflicted && found["added-twice"].conflicted);
+        assert!(found["new"].untracked);
+        assert!(found["build/"].ignored);
+        let src = found["src/"];
+        assert!(src.staged && src.modified && src.untracked);
+
+        // from src/ only what is under it is reported, by its name in src/
+        let (_, found) = parse_status(&raw, "src/");
+        assert_eq!(found.len(), 2);
+        assert!(found["a"].staged && found["a"].modified);
+        assert!(found["b"].untracked);
+    }
+
+    #[test]
+    fn stage_unstage_and_discard() {
+        let dir = utils::TestDir::new();
+        let cwd = dir.to_string_lossy().into_owned();
+        let run = |args: &[&str]| git(&cwd, args).unwrap();
+        run(&["init", "-q", "-b", "main"]);
+        dir.write("a", "a");
+        dir.write("b", "b");
+        run(&["add", "."]);
+        run(&[
+            "-c",
+            "user.name=t",
+            "-c",
+            "user.email=t@t",
+            "commit",
+            "-qm",
+            "init",
+        ]);
+        dir.write("a", "changed");
+        dir.write("b", "changed");
+        let entries = names(&["a", "b"]);
+        let state = |name: &str| {
+            let status = status(&cwd, &entries).unwrap();
+            status
+                .entries
+                .get(name)
+                .map(|s| (s.staged, s.modified))
+                .unwrap_or_default()
+        };
+
+        stage(&cwd, &names(&["a", "b"])).unwrap();
+        assert_eq!(state("a"), (true, false));
+        dir.write("a", "again");
+        assert_eq!(state("a"), (true, true));
+        unstage(&cwd, &names(&["a"])).unwrap();
+        assert_eq!(state("a"), (false, true));
+        discard(&cwd, &names(&["a"])).unwrap();
+        assert_eq!(state("a"), (false, false));
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "a");
+        // discarding leaves staged changes alone
+        discard(&cwd, &names(&["b"])).unwrap();
+        assert_eq!(state("b"), (true, false));
+
+        assert!(stage(&cwd, &names(&["nope"])).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread,
+};
+
+use ignore::{WalkBuilder, WalkState};
+use regex::{Regex, RegexBuilder};
+use tui::widgets::ListState;
+
+// a search stops once it has found this many matching lines
+pub const MAX_MATCHES: usize = 1000;
+
+// lines shown above and below the match in the preview
+const CONTEXT: usize = 5;
+
+// files with a nul byte this early on are taken to be binary and skipped
+const BINARY_CHECK: usize = 8192;
+
+// bigger files are skipped, they are mostly logs, dumps and other data
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+// a matching line, `path` is relative to where the search started
+pub struct Match {
+    pub path: String,
+    pub line: usize,
+    pub text: String,
+}
+
+// searches the contents of every file under a dir, the files are read on a
+// pool of threads and matches stream in as they are found
+pub struct Grep {
+    pub root: PathBuf,
+    pub query: String,
+    // search for the query as is instead of as a regex
+    pub literal: bool,
+    hide: bool,
+    // the query and mode the matches are for
+    searched: Option<(String, bool)>,
+    matches: Vec<Match>,
+    pub state: ListState,
+    // still searching while this is Some
+    results: Option<Receiver<Vec<Match>>>,
+    // tells the search threads to stop
+    cancel: Arc<AtomicBool>,
+    // index of the selected match and its preview
+    preview: Option<(usize, String)>,
+}
+
+impl Grep {
+    // nothing is searched until `search` is called
+    pub fn new(root: PathBuf, hide: bool) -> Grep {
+        Grep {
+            root,
+            query: String::new(),
+            literal: false,
+            hide,
+            searched: None,
+            matches: vec![],
+            state: ListState::default(),
+            results: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+            preview: None,
+        }
+    }
+
+    // whether the query or mode changed since the last search
+    pub fn stale(&self) -> bool {
+        self.searched.as_ref() != Some(&(self.query.clone(), self.literal))
+    }
+
+    // stops the running search, if any, and starts one for the query,
+    // skipping what .gitignore files exclude and dot files when hiding them.
+    // Case is ignored unless the query has an uppercase letter
+    pub fn search(&mut self) -> Result<(), regex::Error> {
+        let pattern = if self.literal {
+            regex::escape(&self.query)
+        } else {
+            self.query.clone()
+        };
+        let re = RegexBuilder::new(&pattern)
+            .case_insensitive(!self.query.chars().any(|c| c.is_uppercase()))
+            .build()?;
+
+        self.cancel.store(true, Ordering::Relaxed);
+        self.cancel = Arc::new(AtomicBool::new(false));
+        self.searched = Some((self.query.clone(), self.literal));
+        self.matches.clear();
+        self.preview = None;
+        self.state.select(None);
+
+        let (tx, rx) = mpsc::channel();
+        self.results = Some(rx);
+        let walk = WalkBuilder::new(&self.root)
+            .hidden(self.hide)
+            .require_git(false)
+            .build_parallel();
+        let root = self.root.clone();
+        let cancel = self.cancel.clone();
+
+        thread::spawn(move || {
+            let found = Arc::new(AtomicUsize::new(0));
+            walk.run(|| {
+                let (tx, re, root) = (tx.clone(), re.clone(), root.clone());
+                let (cancel, found) = (cancel.clone(), found.clone());
+                Box::new(move |entry| {
+                    if cancel.load(Ordering::Relaxed)
+                        || found.load(Ordering::Relaxed) >= MAX_MATCHES
+                    {
+                        return WalkState::Quit;
+                    }
+                    let entry = match entry {
+                        Ok(entry) if entry.file_type().is_some_and(|t| t.is_file()) => entry,
+                        _ => return WalkState::Continue,
+                    };
+                    let matches = search_file(entry.path(), &root, &re);
+                    if matches.is_empty() {
+                        return WalkState::Continue;
+                    }
+                    found.fetch_add(matches.len(), Ordering::Relaxed);
+                    // the search was closed
+                    if tx.send(matches).is_err() {
+                        return WalkState::Quit;
+                    }
+                    WalkState::Continue
+                })
+            });
+        });
+        Ok(())
+    }
+
+    // takes in matches found since the last call, returns whether there were
+    // any or the search finished
+    pub fn poll(&mut self) -> bool {
+        let rx = match &self.results {
+            Some(rx) => rx,
+            None => return false,
+        };
+        let mut received = false;
+        loop {
+            match rx.try_recv() {
+                Ok(matches) => {
+                    let room = MAX_MATCHES.saturating_sub(self.matches.len());
+                    self.matches.extend(matches.into_iter().take(room));
+                    received = true;
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.results = None;
+                    received = true;
+                    break;
+                }
+            }
+        }
+        if self.state.selected().is_none() && !self.matches.is_empty() {
+            self.state.select(Some(0));
+        }
+        received
+    }
+
+    pub fn searching(&self) -> bool {
+        self.results.is_some()
+    }
+
+    pub fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    pub fn selected(&self) -> Option<&Match> {
+        self.matches.get(self.state.selected()?)
+    }
+
+    // moves the selection by `delta`, stopping at either end
+    pub fn scroll(&mut self, delta: isize) {
+        if self.matches.is_empty() {
+            return;
+        }
+        let i = self.state.selected().unwrap_or(0) as isize + delta;
+        self.state
+            .select(Some(i.clamp(0, self.matches.len() as isize - 1) as usize));
+    }
+
+    // the lines around the selected match, read again only when the
+    // selection changes
+    pub fn preview(&mut self) -> &str {
+        let selected = match self.state.selected() {
+            Some(i) if i < self.matches.len() => i,
+            _ => return "",
+        };
+        if self.preview.as_ref().map(|(i, _)| *i) != Some(selected) {
+            let m = &self.matches[selected];
+            let content = context(&self.root.join(&m.path), m.line);
+            self.preview = Some((selected, content));
+        }
+        self.preview.as_ref().map(|(_, c)| c.as_str()).unwrap_or("")
+    }
+}
+
+impl Drop for Grep {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+// the lines of `path` matching `re`, none for binary, unreadable or too big
+// files
+fn search_file(path: &Path, root: &Path, re: &Regex) -> Vec<Match> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return vec![],
+    };
+    if file.metadata().map_or(true, |m| m.len() > MAX_FILE_SIZE) {
+        return vec![];
+    }
+    let mut reader = BufReader::with_capacity(BINARY_CHECK, file);
+    match reader.fill_buf() {
+        Ok(start) if !start.contains(&0) => {}
+        _ => return vec![],
+    }
+
+    let relative = path
+        .strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned();
+    let mut matches = vec![];
+    let mut line = vec![];
+    let mut number = 0;
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => number += 1,
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\n', '\r']);
+        if re.is_match(text) {
+            matches.push(Match {
+                path: relative.clone(),
+                line: number,
+                text: text.trim().to_string(),
+            });
+        }
+    }
+    matches
+}
+
+// numbered lines around `line`, which is marked with a '>'
+fn context(path: &Path, line: usize) -> String {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return e.to_string(),
+    };
+    let first = line.saturating_sub(CONTEXT).max(1);
+    BufReader::new(file)
+        .split(b'\n')
+        .map_while(Result::ok)
+        .enumerate()
+        .skip(first - 1)
+        .take(line + CONTEXT + 1 - first)
+        .map(|(i, text)| {
+            let marker = if i + 1 == line { '>' } else { ' ' };
+            let text = String::from_utf8_lossy(&text);
+            format!("{}{:>5} {}", marker, i + 1, text.trim_end_matches('\r'))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn search_file_finds_matching_lines() {
+        let dir = utils::TestDir::new();
+        let file = dir.write("a.txt", "one\r\ntwo\nthree two\n");
+        let matches = search_file(&file, &dir, &Regex::new("two").unwrap());
+        let found: Vec<(&str, usize, &str)> = matches
+            .iter()
+            .map(|m| (m.path.as_str(), m.line, m.text.as_str()))
+            .collect();
+        assert_eq!(found, [("a.txt", 2, "two"), ("a.txt", 3, "three two")]);
+    }
+
+    #[test]
+    fn search_file_skips_binary_and_big_files() {
+        let dir = utils::TestDir::new();
+        let re = Regex::new("two").unwrap();
+        let binary = dir.write("binary", b"two\0two\n");
+        assert!(search_file(&binary, &dir, &re).is_empty());
+
+        let big = dir.write("big", "two\n");
+        File::options()
+            .append(true)
+            .open(&big)
+            .unwrap()
+            .set_len(MAX_FILE_SIZE + 1)
+            .unwrap();
+        assert!(search_file(&big, &dir, &re).is_empty());
+    }
+
+    #[test]
+    fn context_marks_the_line() {
+        let dir = utils::TestDir::new();
+        let lines: Vec<String> = (1..=20).map(|i| format!("line {}", i)).collect();
+        let file = dir.write("a.txt", lines.join("\n"));
+        let context = context(&file, 10);
+        let context: Vec<&str> = context.lines().collect();
+        assert_eq!(context.len(), 2 * CONTEXT + 1);
+        assert_eq!(context[0], "     5 line 5");
+        assert_eq!(context[CONTEXT], ">   10 line 10");
+    }
+
+    #[test]
+    fn search_is_smart_case() {
+        let dir = utils::TestDir::new();
+        dir.write("a.txt", "Needle\nneedle\n");
+        let mut grep = Grep::new(dir.to_path_buf(), true);
+        for (query, count) in [("needle", 2), ("Needle", 1)] {
+            grep.query = String::from(query);
+            grep.search().unwrap();
+            while grep.searching() {
+                grep.poll();
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            assert_eq!(grep.matches().len(), count, "{}", query);
+            assert!(!grep.stale());
+        }
+    }
+}
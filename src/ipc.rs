@@ -0,0 +1,158 @@
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use crate::utils;
+
+// how long a client gets to send its command after connecting
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+// commands kfm accepts over its socket, one per connection:
+//
+// cd PATH             enter a dir
+// select NAME|PATH    select an entry, cd-ing to its dir first for a path
+// refresh             reload the listing
+// get-selection       marked paths, or the selected path, one per line
+// quit                quit kfm
+//
+// kfm answers with "ok", "error: <reason>" or the selection
+pub const COMMANDS: [&str; 5] = ["cd", "select", "refresh", "get-selection", "quit"];
+
+// a command read from the socket, answered through `reply`
+pub struct Request {
+    pub command: String,
+    pub arg: String,
+    pub reply: Sender<String>,
+}
+
+// per instance socket, in $XDG_RUNTIME_DIR when it is set and otherwise in a
+// private dir of its own, since anyone can create files in /tmp
+pub struct Socket {
+    pub path: PathBuf,
+    // the private dir, removed along with the socket
+    dir: Option<PathBuf>,
+}
+
+impl Socket {
+    pub fn new() -> io::Result<Socket> {
+        match env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) => Ok(Socket {
+                path: Path::new(&dir).join(format!("kfm-{}.sock", std::process::id())),
+                dir: None,
+            }),
+            Err(_) => {
+                let dir = utils::temp_dir("kfm-")?;
+                Ok(Socket {
+                    path: dir.join("kfm.sock"),
+                    dir: Some(dir),
+                })
+            }
+        }
+    }
+
+    pub fn remove(&self) {
+        let _ = fs::remove_file(&self.path);
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+// listens on `path` in the background, handing requests to the main loop
+pub fn serve(path: &Path) -> io::Result<Receiver<Request>> {
+    // a socket left behind by a crashed kfm with the same pid
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // each client on its own thread so a slow one can't hold up the
+            // others
+            let tx = tx.clone();
+            thread::spawn(move || handle(stream, &tx));
+        }
+    });
+
+    Ok(rx)
+}
+
+fn handle(stream: UnixStream, requests: &Sender<Request>) {
+    let mut line = String::new();
+    if stream.set_read_timeout(Some(READ_TIMEOUT)).is_err()
+        || BufReader::new(&stream).read_line(&mut line).is_err()
+    {
+        return;
+    }
+
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+    let (reply_tx, reply_rx) = mpsc::channel();
+    // the main loop is gone when sending fails, the reply says kfm quit
+    let _ = requests.send(Request {
+        command: command.to_string(),
+        arg: arg.to_string(),
+        reply: reply_tx,
+    });
+
+    let reply = reply_rx
+        .recv()
+        .unwrap_or_else(|_| String::from("error: kfm quit"));
+    let _ = (&stream).write_all(reply.as_bytes());
+}
+
+// sends a message to a running kfm and returns its answer
+pub fn send(socket: &str, message: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(message.as_bytes())?;
+    stream.write_all(b"\n")?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn a_silent_client_does_not_block_the_others() {
+        let dir = utils::TestDir::new();
+        let path = dir.join("kfm.sock");
+        let requests = serve(&path).unwrap();
+        thread::spawn(move || {
+            for request in requests {
+                let _ = request
+                    .reply
+                    .send(format!("{} {}", request.command, request.arg));
+            }
+        });
+
+        let _silent = UnixStream::connect(&path).unwrap();
+        let reply = send(&path.to_string_lossy(), "cd /tmp").unwrap();
+        assert_eq!(reply, "cd /tmp");
+    }
+
+    #[test]
+    fn the_fallback_socket_is_in_a_private_dir() {
+        env::remove_var("XDG_RUNTIME_DIR");
+        let socket = Socket::new().unwrap();
+        let dir = socket.path.parent().unwrap().to_path_buf();
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        serve(&socket.path).unwrap();
+        assert!(socket.path.exists());
+        socket.remove();
+        assert!(!dir.exists());
+    }
+}
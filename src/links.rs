@@ -0,0 +1,74 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::utils;
+
+#[derive(Clone)]
+pub struct Link {
+    // as stored in the link, relative targets are relative to its dir
+    pub target: PathBuf,
+    // the target doesn't exist
+    pub broken: bool,
+    // the target is a dir
+    pub dir: bool,
+}
+
+// the symlinks among the entries of `dir`, by file name
+pub fn read(dir: &Path, items: &[String]) -> HashMap<String, Link> {
+    items
+        .iter()
+        .map(|e| utils::entry_name(e))
+        .filter(|name| !name.ends_with('/'))
+        .filter_map(|name| {
+            let path = dir.join(name);
+            let target = fs::read_link(&path).ok()?;
+            let meta = fs::metadata(&path);
+            let link = Link {
+                target,
+                broken: meta.is_err(),
+                dir: meta.is_ok_and(|m| m.is_dir()),
+            };
+            Some((name.to_string(), link))
+        })
+        .collect()
+}
+
+// the path to `link`'s target, from where the link is
+pub fn resolve(link: &Path) -> Option<PathBuf> {
+    let target = fs::read_link(link).ok()?;
+    let dir = link.parent().unwrap_or_else(|| Path::new("/"));
+    Some(dir.join(target))
+}
+
+// `to` as seen from `dir`, both absolute: relative_path("/a/b", "/a/c/d") is
+// "../c/d"
+pub fn relative_path(dir: &Path, to: &Path) -> PathBuf {
+    let dir: Vec<_> = dir.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = dir.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut res = PathBuf::new();
+    for _ in common..dir.len() {
+        res.push("..");
+    }
+    for component in &to[common..] {
+        res.push(component);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths() {
+        let path = |dir: &str, to: &str| relative_path(Path::new(dir), Path::new(to));
+        assert_eq!(path("/a/b", "/a/c/d"), Path::new("../c/d"));
+        assert_eq!(path("/a", "/a/b"), Path::new("b"));
+        assert_eq!(path("/a/b/c", "/x"), Path::new("../../../x"));
+    }
+}
@@ -1,36 +1,172 @@
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use error::Error;
+use finder::Finder;
+use grep::Grep;
+use ops::{LinkKind, Op};
 use state::{App, InputMode};
-use std::{error::Error, io};
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
 use tui::{
     backend::{Backend, CrosstermBackend},
-    layout::{Constraint, Direction, Layout},
-    widgets::{Block, Borders, Clear, Paragraph},
+    layout::{Constraint, Direction, Layout, Rect},
+    widgets::{Block, Borders, Clear, List, ListState, Paragraph},
     Frame, Terminal,
 };
 
+mod batch;
+mod cli;
 mod commands;
+mod error;
+mod finder;
+mod git;
+mod grep;
+mod ipc;
+mod links;
+mod mouse;
+mod ops;
+mod perms;
+mod plugins;
+mod properties;
+mod rename;
+mod sizes;
 mod state;
+mod status;
+mod tmux;
 mod ui;
 mod utils;
 
 // Files
 mod settings;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // setup terminal
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = match cli::parse() {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("kfm: {}\ntry 'kfm --help'", e);
+            std::process::exit(2);
+        }
+    };
+
+    if args.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    if args.version {
+        println!("kfm {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+
+    if let Some(shell) = &args.init {
+        match cli::init_script(shell) {
+            Ok(script) => println!("{}", script),
+            Err(e) => {
+                eprintln!("kfm: {}", e);
+                std::process::exit(2);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(msg) = &args.msg {
+        let socket = match env::var("KFM_SOCKET") {
+            Ok(s) => s,
+            Err(_) => {
+                eprintln!("kfm: KFM_SOCKET is not set, is kfm running?");
+                std::process::exit(1);
+            }
+        };
+        let reply = ipc::send(&socket, msg)?;
+        println!("{}", reply);
+        if reply.starts_with("error") {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(input) = &args.batch {
+        let succeeded = batch::run(input, args.dry_run, args.keep_going)?;
+        std::process::exit(if succeeded { 0 } else { 1 });
+    }
+
+    let mut settings = match settings::get_conf(args.config.as_deref()) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("kfm: {}", e);
+            std::process::exit(2);
+        }
+    };
+    cli::apply(&args, &mut settings);
+
+    // start in the given dir, or in the dir of the given file
+    let mut select = None;
+    let mut cwd = match env::current_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("kfm: can't find the current directory: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Some(path) = &args.path {
+        let path = Path::new(path);
+        let dir = if path.is_dir() {
+            Some(path)
+        } else if path.exists() {
+            select = path.file_name().map(|n| n.to_string_lossy().into_owned());
+            path.parent().filter(|p| !p.as_os_str().is_empty())
+        } else {
+            eprintln!("kfm: {} does not exist", path.display());
+            std::process::exit(2);
+        };
+        if let Some(dir) = dir {
+            match fs::canonicalize(dir).and_then(|d| fs::read_dir(&d).map(|_| d)) {
+                Ok(dir) => cwd = dir,
+                Err(e) => {
+                    eprintln!("kfm: can't enter {}: {}", dir.display(), e);
+                    std::process::exit(2);
+                }
+            }
+        }
+    }
+
+    // listen for remote commands, exporting the socket to everything kfm runs
+    let socket = ipc::Socket::new().ok();
+    let remote = socket.as_ref().and_then(|s| ipc::serve(&s.path).ok());
+    if let (Some(socket), Some(_)) = (&socket, &remote) {
+        env::set_var("KFM_SOCKET", &socket.path);
+    }
+
+    // setup terminal, drawing on the tty when stdout is reserved for chosen paths
     enable_raw_mode()?;
-    let mut stdout = io::stdout();
+    let mut stdout: Box<dyn Write> = match &args.chooser {
+        Some(c) if c.output == "-" => Box::new(OpenOptions::new().write(true).open("/dev/tty")?),
+        _ => Box::new(io::stdout()),
+    };
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     // create app and run it
-    let app = App::new();
-    let res = run_app(&mut terminal, app);
+    let mut app = App::new(settings, cwd);
+    app.chooser = args.chooser.clone();
+    if let Some(name) = select {
+        app.select(&name);
+    }
+    let res = run_app(&mut terminal, &mut app, remote);
+    if let Some(socket) = &socket {
+        socket.remove();
+    }
 
     // restore terminal
     disable_raw_mode()?;
@@ -41,58 +177,133 @@ fn main() -> Result<(), Box<dyn Error>> {
     )?;
     terminal.show_cursor()?;
 
-    if let Err(err) = res {
-        println!("{:?}", err)
+    match res {
+        Ok(chosen) => {
+            if let Some(chooser) = &args.chooser {
+                cli::write_chosen(chooser, &chosen.unwrap_or_default())?;
+            }
+            cli::write_last_dir(&args, &app.working_dir())?
+        }
+        Err(err) => println!("{:?}", err),
     }
 
     Ok(())
 }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
-    let mut hide = true;
+// something for the main loop to handle
+enum Input {
+    Event(Event),
+    Remote(ipc::Request),
+    // nothing happened before the timeout, the screen needs redrawing
+    Timeout,
+    // background work finished some of its results
+    Background,
+}
+
+// waits for a terminal event, a command from the socket or results of
+// background work, giving up when the status message times out
+fn next_input(remote: &Option<Receiver<ipc::Request>>, app: &mut App) -> io::Result<Input> {
+    let start = Instant::now();
+    let timeout = app.status_timeout();
+    loop {
+        if event::poll(Duration::from_millis(50))? {
+            return Ok(Input::Event(event::read()?));
+        }
+        if let Some(request) = remote.as_ref().and_then(|r| r.try_recv().ok()) {
+            return Ok(Input::Remote(request));
+        }
+        if app.poll_background() {
+            return Ok(Input::Background);
+        }
+        if timeout.is_some_and(|t| start.elapsed() >= t) {
+            return Ok(Input::Timeout);
+        }
+    }
+}
+
+// returns the chosen paths when running as a picker
+fn run_app<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    remote: Option<Receiver<ipc::Request>>,
+) -> io::Result<Option<Vec<String>>> {
+    let mut hide = !app.settings.hidden;
     let mut long = false;
+    let mut last_click = None;
     loop {
-        terminal.draw(|f| ui(f, &mut app))?;
+        terminal.draw(|f| ui(f, app, hide))?;
+
+        // redraw once the status message times out
+        let event = match next_input(&remote, app)? {
+            Input::Event(event) => event,
+            Input::Background => continue,
+            Input::Timeout => {
+                app.status = None;
+                continue;
+            }
+            Input::Remote(request) => {
+                if request.command == "quit" {
+                    let _ = request.reply.send(String::from("ok"));
+                    return Ok(None);
+                }
+                let reply = commands::remote(&request, app, hide);
+                let _ = request.reply.send(reply);
+                continue;
+            }
+        };
 
-        if let Event::Key(key) = event::read()? {
+        let event = match event {
+            Event::Mouse(mouse) => match mouse::handle(mouse, app, &mut last_click) {
+                mouse::Action::None => continue,
+                mouse::Action::Key(code) => Event::Key(KeyEvent::new(code, KeyModifiers::NONE)),
+                mouse::Action::Cd(path) => {
+                    hide = !app.settings.hidden;
+                    long = false;
+                    if let Err(e) = commands::enter_dir(path, app, "./", hide) {
+                        app.report(e);
+                    }
+                    continue;
+                }
+            },
+            event => event,
+        };
+
+        if let Event::Key(key) = event {
             match app.input_mode {
                 InputMode::Normal => match key.code {
-                    KeyCode::Char('q') => return Ok(()),
+                    KeyCode::Char('q') => return Ok(None),
                     KeyCode::Char('j') => {
                         app.prev = false;
+                        app.properties = None;
                         app.items.next()
                     }
                     KeyCode::Char('k') => {
                         app.prev = false;
+                        app.properties = None;
                         app.items.previous()
                     }
-                    KeyCode::Char('t') => {
-                        if let Some(selected_file) = app.items.get_selected() {
-                            let current_path = utils::get_working_dir();
-                            let new_path = current_path.clone() + "/" + selected_file;
-                            if selected_file.ends_with("/") {
-                                return commands::tmux(new_path);
-                            } else {
-                                return commands::tmux(current_path);
-                            }
-                        }
+                    KeyCode::Char('t') => commands::tmux(tmux::Target::Window, app),
+                    KeyCode::Char('|') => commands::tmux(tmux::Target::SplitRight, app),
+                    KeyCode::Char('_') => commands::tmux(tmux::Target::SplitBelow, app),
+                    KeyCode::Char('P') => {
+                        commands::tmux(tmux::Target::Popup, app);
+                        terminal.clear()?;
+                    }
+                    KeyCode::Char('a') => {
+                        app.input_mode = InputMode::Editing;
+                        app.input_field_title = String::from("Send To Tmux Pane");
+                        app.input = app.settings.tmux_target.clone().unwrap_or_default();
                     }
                     KeyCode::Backspace => {
-                        let current_path = utils::get_working_dir();
+                        let current_path = app.working_dir();
                         let new_path = current_path + "/" + "../";
-                        commands::enter_dir(new_path, &mut app, "./")
-                            .expect("failed to enter directory");
+                        if let Err(e) = commands::enter_dir(new_path, app, "./", hide) {
+                            app.report(e);
+                        }
                     }
                     KeyCode::Char(' ') => {
-                        if let Some(selected_file) = app.items.get_selected() {
-                            let current_path = utils::get_working_dir();
-                            let new_path = current_path + "/" + selected_file;
-                            let preview: String;
-                            if selected_file.ends_with("/") {
-                                preview = commands::ls(&new_path).join("\n");
-                            } else {
-                                preview = commands::prev_file(new_path);
-                            }
+                        if let Some(selected_file) = app.selected_name().map(String::from) {
+                            let preview = commands::preview(&selected_file, app);
                             app.prev = !app.prev;
                             app.file_cont = preview;
                         }
@@ -111,24 +322,181 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                         long = false;
                         app.new_cwd("./", hide)
                     }
+                    KeyCode::Char('p') => app.toggle_panes(hide, long),
+                    KeyCode::Tab => (hide, long) = app.switch_pane(hide, long),
+                    KeyCode::Char('n') => app.new_tab(hide, long),
+                    KeyCode::Char('w') => (hide, long) = app.close_tab(hide, long),
+                    KeyCode::Char(']') => {
+                        let next = (app.tab + 1) % app.tabs.len();
+                        (hide, long) = app.switch_tab(next, hide, long);
+                    }
+                    KeyCode::Char('[') => {
+                        let previous = (app.tab + app.tabs.len() - 1) % app.tabs.len();
+                        (hide, long) = app.switch_tab(previous, hide, long);
+                    }
+                    KeyCode::Char(c @ '1'..='9') => {
+                        let index = c as usize - '1' as usize;
+                        (hide, long) = app.switch_tab(index, hide, long);
+                    }
                     KeyCode::Enter => {
-                        let current_path = utils::get_working_dir();
-                        hide = true;
+                        let current_path = app.working_dir();
+                        hide = !app.settings.hidden;
                         long = false;
 
-                        if let Some(selected_file) = app.items.get_selected() {
+                        if let Some(selected_file) = app.selected_name() {
                             let new_path = current_path + "/" + selected_file;
 
-                            if utils::is_dir(selected_file) {
-                                commands::enter_dir(new_path, &mut app, "./")
-                                    .expect("failed to enter directory");
+                            // symlinks to dirs are listed without a '/'
+                            let dir = utils::is_dir(selected_file)
+                                || app.links.get(selected_file).is_some_and(|l| l.dir);
+                            let result = if dir {
+                                commands::enter_dir(new_path, app, "./", hide)
+                            } else if app.chooser.is_some() {
+                                return Ok(Some(commands::choose(app)));
                             } else {
-                                commands::enter_file(new_path).expect("failed to enter file");
+                                let result = commands::enter_file(new_path, app.settings.editor());
+                                terminal.clear()?;
+                                app.refresh(hide);
+                                result
+                            };
+                            if let Err(e) = result {
+                                app.report(e);
+                            }
+                        } else {
+                            app.report(Error::NoSelection);
+                        }
+                    }
+                    KeyCode::Char('C') if app.chooser.is_some() => {
+                        return Ok(Some(commands::choose(app)));
+                    }
+                    KeyCode::Char('s') if app.git.is_some() => commands::git(git::stage, app, hide),
+                    KeyCode::Char('u') if app.git.is_some() => {
+                        commands::git(git::unstage, app, hide)
+                    }
+                    KeyCode::Char('X') if app.git.is_some() => {
+                        let paths = commands::selected_or_marked(app);
+                        if !paths.is_empty() {
+                            app.input_mode = InputMode::Discarding;
+                            app.input_field_title = if paths.len() == 1 {
+                                format!("Discard changes to {}", paths[0])
+                            } else {
+                                format!("Discard changes to {} files", paths.len())
+                            };
+                        }
+                    }
+                    KeyCode::Char('=') if app.git.is_some() => {
+                        if let Some(selected_file) = app.selected_name() {
+                            let path = commands::absolute_path(&app.cwd, selected_file);
+                            app.file_cont = git::diff(&app.working_dir(), &path);
+                            app.prev = true;
+                        }
+                    }
+                    KeyCode::Char('r') => {
+                        if let Some(selected_file) = app.selected_name() {
+                            if selected_file != "./" && selected_file != "../" {
+                                app.input = selected_file.trim_end_matches('/').to_string();
+                                app.input_mode = InputMode::Editing;
+                                app.input_field_title = String::from("Rename");
+                            }
+                        }
+                    }
+                    KeyCode::Char('x') => {
+                        let paths: Vec<PathBuf> = commands::selected_or_marked(app)
+                            .iter()
+                            .map(PathBuf::from)
+                            .collect();
+                        if !paths.is_empty() {
+                            commands::run_op(Op::Trash(paths), app, hide);
+                        }
+                    }
+                    KeyCode::Char('c') => {
+                        app.input = commands::destination_dir(app);
+                        app.input_mode = InputMode::Editing;
+                        app.input_field_title = String::from("Copy To");
+                    }
+                    KeyCode::Char('m') => {
+                        app.input = commands::destination_dir(app);
+                        app.input_mode = InputMode::Editing;
+                        app.input_field_title = String::from("Move To");
+                    }
+                    KeyCode::Char('v') => {
+                        if let Some(selected_file) = app.selected_name() {
+                            if selected_file != "./" && selected_file != "../" {
+                                let current_path = app.cwd.to_string_lossy().into_owned();
+                                let mark_path = current_path + "/" + selected_file;
+                                app.toggle_mark(mark_path);
                             }
+                        }
+                        app.items.next()
+                    }
+                    KeyCode::Char('!') => {
+                        app.input_mode = InputMode::Editing;
+                        app.input_field_title = String::from("Shell Command");
+                    }
+                    KeyCode::Char('S') => {
+                        app.input_mode = InputMode::Editing;
+                        app.input_field_title = String::from("Interactive Shell Command");
+                    }
+                    KeyCode::Esc => {
+                        app.help = false;
+                        app.prev = false;
+                        app.error_log = false;
+                        app.properties = None;
+                    }
+                    KeyCode::Char('i') => commands::toggle_properties(app),
+                    KeyCode::Char('z') => app.size_dirs(),
+                    KeyCode::Char('U') => {
+                        long = false;
+                        app.toggle_usage(hide);
+                    }
+                    KeyCode::Char('E') => {
+                        app.error_log = !app.error_log;
+                    }
+                    KeyCode::Char('R') => {
+                        let result = commands::bulk_rename(app);
+                        terminal.clear()?;
+                        if let Err(e) = result {
+                            app.report(e);
+                        }
+                    }
+                    KeyCode::Char('M') => {
+                        if let Err(e) = commands::edit_perms(app) {
+                            app.report(e);
+                        }
+                    }
+                    KeyCode::Char('J') => match commands::follow_link(app, !app.settings.hidden) {
+                        Ok(hidden) => {
+                            hide = hidden;
+                            long = false;
+                        }
+                        Err(e) => app.report(e),
+                    },
+                    KeyCode::Char('L') => {
+                        if app.marked.is_empty() {
+                            app.report(Error::Op(String::from("nothing marked to link to")));
                         } else {
-                            println!("No file/directory currently selected");
+                            app.input_mode = InputMode::Linking;
+                            app.input_field_title = format!(
+                                "Link {} marked here: (a)bsolute, (r)elative or (h)ard?",
+                                app.marked.len()
+                            );
                         }
                     }
+                    KeyCode::Char('g') => {
+                        app.grep = Some(Grep::new(app.cwd.clone(), hide));
+                        app.input_mode = InputMode::Grepping;
+                    }
+                    KeyCode::Char('f') => {
+                        app.finder = Some(Finder::start(app.cwd.clone(), hide));
+                        app.input_mode = InputMode::Finding;
+                    }
+                    KeyCode::Char('?') => {
+                        app.help = !app.help;
+                    }
+                    KeyCode::Char(':') => {
+                        app.input_mode = InputMode::Editing;
+                        app.input_field_title = String::from("Run Command");
+                    }
                     KeyCode::Char('%') => {
                         app.input_mode = InputMode::Editing;
                         app.input_field_title = String::from("Enter Filename");
@@ -138,17 +506,27 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                         app.input_field_title = String::from("Enter Directory Name");
                     }
                     KeyCode::Char('D') => {
-                        if let Some(selected_file) = app.items.get_selected() {
+                        if let Some(selected_file) = app.selected_name().map(String::from) {
                             if selected_file != "./" && selected_file != "../" {
                                 app.input_mode = InputMode::Deleting;
 
-                                let current_path = utils::get_working_dir();
-                                let deletion_path = current_path + "/" + selected_file;
+                                let current_path = app.cwd.to_string_lossy().into_owned();
+                                let deletion_path = current_path + "/" + &selected_file;
                                 let title = "Confirm deletion of ".to_string() + &deletion_path;
                                 app.input_field_title = title;
                             }
                         } else {
-                            println!("No file/directory currently selected");
+                            app.report(Error::NoSelection);
+                        }
+                    }
+                    KeyCode::Char(c) => {
+                        if let Some(name) = app.command_for_key(c) {
+                            if let Err(e) = commands::start_user_command(&name, app, hide) {
+                                app.report(e);
+                            }
+                            terminal.clear()?;
+                        } else if let Some(name) = app.plugin_for_key(c) {
+                            plugins::run_action(&name, app, hide);
                         }
                     }
                     _ => {}
@@ -161,35 +539,219 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                         app.input.pop();
                     }
                     KeyCode::Esc => {
-                        commands::restore_input_field(&mut app);
+                        commands::restore_input_field(app);
                     }
-                    KeyCode::Enter => {
-                        if app.input.len() != 0 {
-                            let current_path = utils::get_working_dir();
-                            let new_path = current_path + "/" + &app.input;
-                            let title = app.input_field_title.clone();
+                    KeyCode::Enter if !app.input.is_empty() => {
+                        let current_path = app.working_dir();
+                        let new_path = current_path.clone() + "/" + &app.input;
+                        let title = app.input_field_title.clone();
+
+                        if title == "Rename" {
+                            let op = app.selected_name().map(|f| {
+                                Op::Rename(
+                                    PathBuf::from(commands::absolute_path(&app.cwd, f)),
+                                    app.input.clone(),
+                                )
+                            });
+                            match op {
+                                Some(op) => commands::run_op(op, app, hide),
+                                None => commands::restore_input_field(app),
+                            }
+                        } else if title == "Copy To" || title == "Move To" {
+                            let dest = Path::new(&current_path).join(&app.input);
+                            let sources: Vec<PathBuf> = commands::selected_or_marked(app)
+                                .iter()
+                                .map(PathBuf::from)
+                                .collect();
+                            if sources.is_empty() {
+                                commands::restore_input_field(app);
+                            } else if title == "Copy To" {
+                                commands::run_op(Op::Copy(sources, dest), app, hide);
+                            } else {
+                                commands::run_op(Op::Move(sources, dest), app, hide);
+                            }
+                        } else if title == "Send To Tmux Pane" {
+                            let target = tmux::Target::Pane(app.input.clone());
+                            commands::restore_input_field(app);
+                            commands::tmux(target, app);
+                        } else if title == "Run Command" {
+                            let name = app.input.clone();
+                            commands::restore_input_field(app);
+                            if let Err(e) = commands::start_user_command(&name, app, hide) {
+                                app.report(e);
+                            }
+                            terminal.clear()?;
+                        } else if title == "Shell Command" || title == "Interactive Shell Command" {
+                            let cmd = commands::expand_for_app(&app.input.clone(), app);
+                            commands::restore_input_field(app);
 
-                            if title == String::from("Enter Filename") {
-                                commands::create_file(new_path);
+                            if title == "Shell Command" {
+                                app.file_cont = commands::shell(&cmd, &current_path);
+                                app.prev = true;
                             } else {
-                                commands::create_dir(new_path, &mut app, hide.clone());
+                                if let Err(e) = commands::shell_interactive(&cmd, &current_path) {
+                                    app.report(e);
+                                }
+                                terminal.clear()?;
                             }
+                            app.new_cwd("./", hide);
+                        } else if title == "Enter Filename" {
+                            commands::create_file(new_path, app, hide);
+                            terminal.clear()?;
+                        } else {
+                            commands::create_dir(new_path, app, hide);
                         }
                     }
                     _ => {}
                 },
                 InputMode::Deleting => match key.code {
                     KeyCode::Char('y') => {
-                        let path = utils::get_working_dir();
-                        if let Some(selected) = app.items.get_selected() {
+                        let path = app.working_dir();
+                        if let Some(selected) = app.selected_name() {
                             let delete_path = path + "/" + selected;
-                            commands::delete(delete_path, &mut app, hide.clone())
+                            commands::delete(delete_path, app, hide)
                         } else {
-                            println!("No file/directory currently selected");
+                            commands::restore_input_field(app);
+                            app.report(Error::NoSelection);
                         }
                     }
                     KeyCode::Char('n') => {
-                        commands::restore_input_field(&mut app);
+                        commands::restore_input_field(app);
+                    }
+                    _ => {}
+                },
+                InputMode::Finding => {
+                    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                    let finder = match app.finder.as_mut() {
+                        Some(finder) => finder,
+                        None => continue,
+                    };
+                    if let Some(delta) = search_scroll(&key) {
+                        finder.scroll(delta);
+                        continue;
+                    }
+                    match key.code {
+                        KeyCode::Esc => {
+                            app.finder = None;
+                            app.input_mode = InputMode::Normal;
+                        }
+                        KeyCode::Enter => {
+                            long = false;
+                            if let Err(e) = commands::finder_jump(app, false, hide) {
+                                app.report(e);
+                            }
+                        }
+                        KeyCode::Char('o') if ctrl => {
+                            long = false;
+                            let result = commands::finder_jump(app, true, hide);
+                            terminal.clear()?;
+                            if let Err(e) = result {
+                                app.report(e);
+                            }
+                        }
+                        KeyCode::Backspace => {
+                            let mut query = finder.query.clone();
+                            query.pop();
+                            finder.set_query(query);
+                        }
+                        KeyCode::Char(c) => {
+                            finder.set_query(format!("{}{}", finder.query, c));
+                        }
+                        _ => {}
+                    }
+                }
+                InputMode::Grepping => {
+                    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                    let grep = match app.grep.as_mut() {
+                        Some(grep) => grep,
+                        None => continue,
+                    };
+                    if let Some(delta) = search_scroll(&key) {
+                        grep.scroll(delta);
+                        continue;
+                    }
+                    match key.code {
+                        KeyCode::Esc => {
+                            app.grep = None;
+                            app.input_mode = InputMode::Normal;
+                        }
+                        KeyCode::Enter => {
+                            // a search that is up to date opens the editor
+                            let opens = !grep.stale();
+                            let result = commands::grep_enter(app);
+                            if opens {
+                                terminal.clear()?;
+                            }
+                            if let Err(e) = result {
+                                app.report(e);
+                            }
+                        }
+                        KeyCode::Char('t') if ctrl => grep.literal = !grep.literal,
+                        KeyCode::Backspace => {
+                            grep.query.pop();
+                        }
+                        KeyCode::Char(c) => grep.query.push(c),
+                        _ => {}
+                    }
+                }
+                InputMode::Permissions => {
+                    let editor = match app.perms.as_mut() {
+                        Some(editor) => editor,
+                        None => continue,
+                    };
+                    match key.code {
+                        KeyCode::Esc => {
+                            app.perms = None;
+                            app.input_mode = InputMode::Normal;
+                        }
+                        KeyCode::Enter => commands::apply_perms(app, hide),
+                        KeyCode::Down | KeyCode::Right | KeyCode::Tab => editor.move_focus(1),
+                        KeyCode::Up | KeyCode::Left | KeyCode::BackTab => editor.move_focus(-1),
+                        KeyCode::Backspace => editor.backspace(),
+                        KeyCode::Char(c) => editor.type_char(c),
+                        _ => {}
+                    }
+                }
+                InputMode::Linking => match key.code {
+                    KeyCode::Char('a') => commands::link_marked(LinkKind::Absolute, app, hide),
+                    KeyCode::Char('r') => commands::link_marked(LinkKind::Relative, app, hide),
+                    KeyCode::Char('h') => commands::link_marked(LinkKind::Hard, app, hide),
+                    KeyCode::Char('n') | KeyCode::Esc => commands::restore_input_field(app),
+                    _ => {}
+                },
+                InputMode::Renaming => match key.code {
+                    KeyCode::Char('y') => commands::finish_bulk_rename(app, hide),
+                    KeyCode::Char('n') | KeyCode::Esc => {
+                        commands::restore_input_field(app);
+                        app.prev = false;
+                    }
+                    _ => {}
+                },
+                InputMode::Discarding => match key.code {
+                    KeyCode::Char('y') => commands::git(git::discard, app, hide),
+                    KeyCode::Char('n') | KeyCode::Esc => {
+                        commands::restore_input_field(app);
+                    }
+                    _ => {}
+                },
+                InputMode::Confirming => match key.code {
+                    KeyCode::Char('y') => {
+                        let command = app
+                            .pending_command
+                            .as_ref()
+                            .and_then(|name| app.settings.commands.get(name))
+                            .cloned();
+                        if let Some(command) = command {
+                            if let Err(e) = commands::run_user_command(&command, app, hide) {
+                                app.report(e);
+                            }
+                            terminal.clear()?;
+                        } else {
+                            commands::restore_input_field(app);
+                        }
+                    }
+                    KeyCode::Char('n') | KeyCode::Esc => {
+                        commands::restore_input_field(app);
                     }
                     _ => {}
                 },
@@ -198,64 +760,268 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
     }
 }
 
-fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
-    let input_mode = &app.input_mode;
-    let mut layout_constraints = [
-        Constraint::Length(3),
-        Constraint::Min(0),
-        Constraint::Length(3),
-    ]
-    .to_vec();
-
-    match input_mode {
-        // hide input bar if in normal mode
-        InputMode::Normal => {
-            layout_constraints.pop();
+fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App, hide: bool) {
+    // the status bar shows the latest message while there is one
+    let status = match app.status_message() {
+        Some(message) => ui::status_line(message.to_string()),
+        None => {
+            let template = app.settings.status_format().to_string();
+            ui::status_bar(status::format(&template, app, hide))
         }
-        InputMode::Deleting => {
-            layout_constraints[2] = Constraint::Length(1);
+    };
+
+    let input_mode = &app.input_mode;
+    // the input field is hidden in normal mode, the finder has its own, and
+    // prompts only need a line
+    let input_height = match input_mode {
+        InputMode::Normal | InputMode::Finding | InputMode::Grepping | InputMode::Permissions => {
+            None
         }
-        InputMode::Editing => {}
+        InputMode::Deleting
+        | InputMode::Confirming
+        | InputMode::Discarding
+        | InputMode::Renaming
+        | InputMode::Linking => Some(1),
+        InputMode::Editing => Some(3),
+    };
+    // the compact layout drops the quick help
+    let compact = app.settings.layout == settings::Layout::Compact;
+
+    // quick help, listing, input field and the status bar at the bottom
+    let mut layout_constraints = vec![];
+    if !compact {
+        layout_constraints.push(Constraint::Length(3));
+    }
+    layout_constraints.push(Constraint::Min(0));
+    if let Some(height) = input_height {
+        layout_constraints.push(Constraint::Length(height));
     }
+    layout_constraints.push(Constraint::Length(1));
 
     let size = f.size();
     let layout = Layout::default()
         .direction(Direction::Vertical)
-        .constraints(layout_constraints.as_ref())
-        .split(f.size());
+        .constraints(layout_constraints)
+        .split(size);
+    let mut areas = layout.into_iter();
+    let help_area = if compact { None } else { areas.next() };
+    let mut nav_area = areas.next().unwrap_or_default();
+    let input_area = input_height.and_then(|_| areas.next()).unwrap_or_default();
+    let status_area = areas.next().unwrap_or_default();
 
     // create quick help widget
-    let quick_help_widget = ui::quick_help();
-    f.render_widget(quick_help_widget, layout[0]);
+    if let Some(area) = help_area {
+        f.render_widget(ui::quick_help(), area);
+    }
+
+    // the tab bar takes the top line of the navigation area once there are
+    // several tabs
+    if app.tabs.len() > 1 {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Min(0)])
+            .split(nav_area);
+        f.render_widget(ui::tab_bar(app.tab_titles(), app.tab), chunks[0]);
+        nav_area = chunks[1];
+    }
+
+    // the dual-pane layout splits the navigation area in two, the unfocused
+    // pane is drawn from its saved listing
+    if let Some(other) = &app.other_pane {
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(nav_area);
+        let (focused, unfocused) = if app.right_focused {
+            (chunks[1], chunks[0])
+        } else {
+            (chunks[0], chunks[1])
+        };
+        let other_widget = ui::navigation_window(
+            &other.cwd.to_string_lossy(),
+            other.files(),
+            &app.marked,
+            other.columns(),
+            other.git(),
+            &app.sizes,
+            other.links(),
+        );
+        f.render_stateful_widget(other_widget, unfocused, &mut other.list_state());
+        app.other_pane_area = unfocused;
+        nav_area = focused;
+    } else {
+        app.other_pane_area = Rect::default();
+    }
+    app.nav_area = nav_area;
+    app.items
+        .sync_offset(nav_area.height.saturating_sub(2) as usize);
 
     // create navigation window widget
+    let cwd = app.working_dir();
     let state = app.current_files();
-    let nav_window_widget = ui::navigation_window(&state);
-    f.render_stateful_widget(nav_window_widget, layout[1], &mut app.items.state);
+    let nav_window_widget = if app.usage {
+        let sizes: Vec<Option<u64>> = state.iter().map(|e| app.entry_size(e)).collect();
+        ui::usage_window(&cwd, &state, &sizes, &app.marked, app.git.as_ref())
+    } else {
+        ui::navigation_window(
+            &cwd,
+            &state,
+            &app.marked,
+            &app.columns,
+            app.git.as_ref(),
+            &app.sizes,
+            &app.links,
+        )
+    };
+    f.render_stateful_widget(nav_window_widget, nav_area, &mut app.items.state);
 
     // create input field widget
     match input_mode {
-        InputMode::Normal => {}
-        InputMode::Deleting => {
+        InputMode::Normal | InputMode::Finding | InputMode::Grepping | InputMode::Permissions => {}
+        InputMode::Deleting
+        | InputMode::Confirming
+        | InputMode::Discarding
+        | InputMode::Renaming => {
             let prompt = app.input_field_title.clone();
             let delete_prompt_widget = ui::delete_prompt(&prompt);
-            f.render_widget(delete_prompt_widget, layout[2]);
+            f.render_widget(delete_prompt_widget, input_area);
+        }
+        InputMode::Linking => {
+            let prompt = ui::choice_prompt(&app.input_field_title);
+            f.render_widget(prompt, input_area);
         }
         InputMode::Editing => {
             let input = &app.input;
             let input_title = app.input_field_title.clone();
             let input_field_widget = ui::input_field(input, input_title);
-            f.render_widget(input_field_widget, layout[2]);
+            f.render_widget(input_field_widget, input_area);
         }
     }
 
     if app.prev {
         let block = Paragraph::new(app.file_cont.clone())
             .block(Block::default().borders(Borders::ALL).title(" Preview "))
-            .alignment(tui::layout::Alignment::Left);
+            .alignment(tui::layout::Alignment::Left)
+            .scroll((app.preview_scroll, 0));
         let area = centered_rect(80, 80, size);
+        app.preview_area = area;
         f.render_widget(Clear, area);
         f.render_widget(block, area);
+    } else {
+        app.preview_scroll = 0;
+    }
+
+    f.render_widget(status, status_area);
+
+    if let Some(finder) = app.finder.as_mut() {
+        let title = format!(
+            "Find ({}/{}{})",
+            finder.match_count(),
+            finder.total(),
+            if finder.walking() { "..." } else { "" }
+        );
+        let preview = finder.preview().to_string();
+        finder.state = search_popup(
+            f,
+            ui::input_field(&finder.query, title),
+            ui::finder_list(finder.matches()),
+            finder.state.clone(),
+            Paragraph::new(preview),
+            String::from(" Preview "),
+        );
+    }
+
+    if let Some(grep) = app.grep.as_mut() {
+        let count = grep.matches().len();
+        let title = format!(
+            "Grep {} ({}{}{})",
+            if grep.literal { "literal" } else { "regex" },
+            count,
+            if count >= grep::MAX_MATCHES { "+" } else { "" },
+            if grep.searching() { "..." } else { "" }
+        );
+        let preview = grep.preview().to_string();
+        let preview_title = grep
+            .selected()
+            .map(|m| format!(" {}:{} ", m.path, m.line))
+            .unwrap_or_default();
+        grep.state = search_popup(
+            f,
+            ui::input_field(&grep.query, title),
+            ui::grep_list(grep.matches()),
+            grep.state.clone(),
+            Paragraph::new(preview),
+            preview_title,
+        );
+    }
+
+    if let Some(editor) = &app.perms {
+        let area = centered_rect(50, 60, size);
+        f.render_widget(Clear, area);
+        f.render_widget(ui::perms_editor(editor), area);
+    }
+
+    if let Some(rows) = &app.properties {
+        let area = centered_rect(70, 60, size);
+        f.render_widget(Clear, area);
+        f.render_widget(ui::properties(rows), area);
+    }
+
+    if app.error_log {
+        let log_widget = ui::error_log(&app.errors);
+        let area = centered_rect(80, 60, size);
+        f.render_widget(Clear, area);
+        f.render_widget(log_widget, area);
+    }
+
+    if app.help {
+        let help_widget = ui::help(&app.settings.commands, &app.settings.plugins);
+        let area = centered_rect(60, 80, size);
+        f.render_widget(Clear, area);
+        f.render_widget(help_widget, area);
+    }
+}
+
+// the finder and grep popup: the query on top, the results on the left and a
+// preview of the selected one on the right. The results borrow whatever owns
+// their list state, so it is scrolled as a copy that is handed back
+fn search_popup<B: Backend>(
+    f: &mut Frame<B>,
+    query: Paragraph,
+    results: List,
+    mut state: ListState,
+    preview: Paragraph,
+    preview_title: String,
+) -> ListState {
+    let area = centered_rect(90, 90, f.size());
+    f.render_widget(Clear, area);
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
+        .split(area);
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(rows[1]);
+
+    f.render_widget(query, rows[0]);
+    f.render_stateful_widget(results, columns[0], &mut state);
+    let preview = preview.block(Block::default().borders(Borders::ALL).title(preview_title));
+    f.render_widget(preview, columns[1]);
+    state
+}
+
+// how far a key moves the selection in the finder and grep popups
+fn search_scroll(key: &KeyEvent) -> Option<isize> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+        KeyCode::Down | KeyCode::Tab => Some(1),
+        KeyCode::Char('n') if ctrl => Some(1),
+        KeyCode::Up | KeyCode::BackTab => Some(-1),
+        KeyCode::Char('p') if ctrl => Some(-1),
+        KeyCode::PageDown => Some(10),
+        KeyCode::PageUp => Some(-10),
+        _ => None,
     }
 }
 
@@ -0,0 +1,256 @@
+use std::time::{Duration, Instant};
+
+use crossterm::event::{KeyCode, MouseButton, MouseEvent, MouseEventKind};
+use tui::layout::Rect;
+
+use crate::state::{App, InputMode};
+
+// two clicks on the same entry within this time open it
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+// lines moved per wheel notch
+const SCROLL_LINES: u16 = 3;
+
+// what the main loop should do about a mouse event
+pub enum Action {
+    None,
+    // handle it as if the key was pressed
+    Key(KeyCode),
+    // enter the given dir
+    Cd(String),
+}
+
+// the last click on an entry of the list
+pub struct Click {
+    index: usize,
+    at: Instant,
+}
+
+pub fn handle(event: MouseEvent, app: &mut App, last_click: &mut Option<Click>) -> Action {
+    // prompts and the help popup keep the keyboard only
+    if !matches!(app.input_mode, InputMode::Normal) || app.help {
+        return Action::None;
+    }
+
+    let (column, row) = (event.column, event.row);
+    match event.kind {
+        MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
+            let down = matches!(event.kind, MouseEventKind::ScrollDown);
+            if app.prev && contains(app.preview_area, column, row) {
+                app.preview_scroll = if down {
+                    app.preview_scroll.saturating_add(SCROLL_LINES)
+                } else {
+                    app.preview_scroll.saturating_sub(SCROLL_LINES)
+                };
+            } else if contains(app.nav_area, column, row) {
+                let lines = SCROLL_LINES as isize;
+                app.items.scroll(if down { lines } else { -lines });
+            }
+            Action::None
+        }
+        MouseEventKind::Down(MouseButton::Left) => {
+            // clicking outside the preview closes it
+            if app.prev {
+                if !contains(app.preview_area, column, row) {
+                    app.prev = false;
+                }
+                return Action::None;
+            }
+
+            if contains(app.other_pane_area, column, row) {
+                return Action::Key(KeyCode::Tab);
+            }
+
+            let area = app.nav_area;
+            if row == area.y && column > area.x {
+                return match breadcrumb(&app.working_dir(), column - area.x - 1) {
+                    Some(path) => Action::Cd(path),
+                    None => Action::None,
+                };
+            }
+
+            // rows inside the borders
+            if !contains(inner(area), column, row) {
+                return Action::None;
+            }
+            let index = match app.items.index_at((row - area.y - 1) as usize) {
+                Some(index) => index,
+                None => return Action::None,
+            };
+            app.items.state.select(Some(index));
+
+            let double =
+                matches!(last_click, Some(c) if c.index == index && c.at.elapsed() < DOUBLE_CLICK);
+            if double {
+                *last_click = None;
+                Action::Key(KeyCode::Enter)
+            } else {
+                *last_click = Some(Click {
+                    index,
+                    at: Instant::now(),
+                });
+                Action::None
+            }
+        }
+        _ => Action::None,
+    }
+}
+
+fn contains(area: Rect, column: u16, row: u16) -> bool {
+    column >= area.x && column < area.right() && row >= area.y && row < area.bottom()
+}
+
+// the area inside a block's borders
+fn inner(area: Rect) -> Rect {
+    Rect {
+        x: area.x + 1,
+        y: area.y + 1,
+        width: area.width.saturating_sub(2),
+        height: area.height.saturating_sub(2),
+    }
+}
+
+// the ancestor of `cwd` whose name is drawn at `offset` in the title, the
+// leading '/' stands for the root
+fn breadcrumb(cwd: &str, offset: u16) -> Option<String> {
+    let (start, _) = cwd.char_indices().nth(offset as usize)?;
+    if start == 0 {
+        return Some(String::from("/"));
+    }
+    let end = cwd[start..]
+        .find('/')
+        .map(|i| i + start)
+        .unwrap_or(cwd.len());
+    Some(cwd[..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyModifiers;
+
+    use super::*;
+    use crate::{settings::Settings, utils::TestDir};
+
+    fn event(kind: MouseEventKind, column: u16, row: u16) -> MouseEvent {
+        MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    fn click(column: u16, row: u16) -> MouseEvent {
+        event(MouseEventKind::Down(MouseButton::Left), column, row)
+    }
+
+    // a listing of ./, ../ and six files drawn in a 40x10 block at the top left
+    fn app(dir: &TestDir) -> App {
+        for file in ["a", "b", "c", "d", "e", "f"] {
+            dir.write(file, "");
+        }
+        let mut app = App::new(Settings::default(), dir.to_path_buf());
+        app.nav_area = Rect::new(0, 0, 40, 10);
+        app.other_pane_area = Rect::new(40, 0, 40, 10);
+        app.preview_area = Rect::new(10, 2, 20, 6);
+        app
+    }
+
+    #[test]
+    fn clicks_select_and_double_clicks_open() {
+        let dir = TestDir::new();
+        let mut app = app(&dir);
+        let mut last = None;
+
+        // the first row inside the border is the first entry
+        assert!(matches!(
+            handle(click(5, 3), &mut app, &mut last),
+            Action::None
+        ));
+        assert_eq!(app.items.state.selected(), Some(2));
+        assert!(matches!(
+            handle(click(5, 3), &mut app, &mut last),
+            Action::Key(KeyCode::Enter)
+        ));
+        // a click on another entry starts over
+        handle(click(5, 3), &mut app, &mut last);
+        assert!(matches!(
+            handle(click(5, 4), &mut app, &mut last),
+            Action::None
+        ));
+        assert_eq!(app.items.state.selected(), Some(3));
+
+        // borders and rows past the last entry do nothing
+        handle(click(0, 4), &mut app, &mut last);
+        handle(click(5, 9), &mut app, &mut last);
+        assert_eq!(app.items.state.selected(), Some(3));
+
+        assert!(matches!(
+            handle(click(45, 4), &mut app, &mut last),
+            Action::Key(KeyCode::Tab)
+        ));
+    }
+
+    #[test]
+    fn title_clicks_go_to_the_dir_under_them() {
+        let dir = TestDir::new();
+        let mut app = app(&dir);
+        let cwd = app.working_dir();
+        let mut last = None;
+
+        assert!(matches!(handle(click(1, 0), &mut app, &mut last), Action::Cd(p) if p == "/"));
+        let top = &cwd[..cwd[1..].find('/').unwrap() + 1];
+        assert!(matches!(handle(click(2, 0), &mut app, &mut last), Action::Cd(p) if p == top));
+        let end = cwd.len() as u16;
+        assert!(matches!(handle(click(end, 0), &mut app, &mut last), Action::Cd(p) if p == cwd));
+        assert!(matches!(
+            handle(click(end + 1, 0), &mut app, &mut last),
+            Action::None
+        ));
+    }
+
+    #[test]
+    fn the_wheel_scrolls_what_is_under_it() {
+        let dir = TestDir::new();
+        let mut app = app(&dir);
+        let mut last = None;
+        app.items.state.select(Some(0));
+
+        handle(event(MouseEventKind::ScrollDown, 5, 5), &mut app, &mut last);
+        assert_eq!(app.items.state.selected(), Some(3));
+        handle(event(MouseEventKind::ScrollDown, 5, 5), &mut app, &mut last);
+        handle(event(MouseEventKind::ScrollDown, 5, 5), &mut app, &mut last);
+        assert_eq!(app.items.state.selected(), Some(7));
+        handle(event(MouseEventKind::ScrollUp, 5, 5), &mut app, &mut last);
+        assert_eq!(app.items.state.selected(), Some(4));
+
+        // with the preview open the wheel scrolls it and a click outside closes it
+        app.prev = true;
+        handle(
+            event(MouseEventKind::ScrollDown, 15, 5),
+            &mut app,
+            &mut last,
+        );
+        assert_eq!(app.preview_scroll, SCROLL_LINES);
+        assert_eq!(app.items.state.selected(), Some(4));
+        handle(click(15, 5), &mut app, &mut last);
+        assert!(app.prev);
+        handle(click(5, 5), &mut app, &mut last);
+        assert!(!app.prev);
+        assert_eq!(app.items.state.selected(), Some(4));
+    }
+
+    #[test]
+    fn prompts_and_help_ignore_the_mouse() {
+        let dir = TestDir::new();
+        let mut app = app(&dir);
+        let mut last = None;
+        app.items.state.select(Some(0));
+        app.help = true;
+        handle(click(5, 4), &mut app, &mut last);
+        app.help = false;
+        app.input_mode = InputMode::Editing;
+        handle(click(5, 4), &mut app, &mut last);
+        assert_eq!(app.items.state.selected(), Some(0));
+    }
+}
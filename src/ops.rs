@@ -0,0 +1,430 @@
+use std::{
+    collections::HashSet,
+    env, fs,
+    io::ErrorKind,
+    os::unix::{ffi::OsStrExt, fs::symlink},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{links, status};
+
+// file operations shared by the tui and batch mode
+pub enum Op {
+    Mkdir(PathBuf),
+    Touch(PathBuf),
+    // sources, destination
+    Copy(Vec<PathBuf>, PathBuf),
+    Move(Vec<PathBuf>, PathBuf),
+    Trash(Vec<PathBuf>),
+    // path, new name
+    Rename(PathBuf, String),
+    // links to the sources, made in the destination dir
+    Link(Vec<PathBuf>, PathBuf, LinkKind),
+}
+
+#[derive(Clone, Copy)]
+pub enum LinkKind {
+    // a symlink with the source's absolute path
+    Absolute,
+    // a symlink with the path to the source from the destination dir
+    Relative,
+    Hard,
+}
+
+// the filesystem as it would look after the operations checked so far, so a
+// dry run of several dependent operations can be validated as a whole
+#[derive(Default)]
+pub struct Plan {
+    created: HashSet<PathBuf>,
+    // created paths that are dirs
+    dirs: HashSet<PathBuf>,
+    removed: HashSet<PathBuf>,
+}
+
+impl Plan {
+    fn exists(&self, path: &Path) -> bool {
+        if self.created.contains(path) {
+            return true;
+        }
+        // anything under a removed path is gone too
+        if path.ancestors().any(|a| self.removed.contains(a)) {
+            return false;
+        }
+        path.symlink_metadata().is_ok()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.dirs.contains(path)
+            || (self.exists(path) && !self.created.contains(path) && path.is_dir())
+    }
+
+    fn create(&mut self, path: PathBuf, dir: bool) {
+        self.removed.remove(&path);
+        if dir {
+            self.dirs.insert(path.clone());
+        }
+        self.created.insert(path);
+    }
+
+    fn remove(&mut self, path: &Path) {
+        self.created.remove(path);
+        self.dirs.remove(path);
+        self.removed.insert(path.to_path_buf());
+    }
+}
+
+impl Op {
+    // validates the operation against `plan`, updating it as if the
+    // operation had run
+    pub fn check(&self, plan: &mut Plan) -> Result<(), String> {
+        match self {
+            Op::Mkdir(path) => {
+                if plan.exists(path) && !plan.is_dir(path) {
+                    return Err(format!("{} exists and is not a directory", path.display()));
+                }
+                for dir in path.ancestors().filter(|a| !a.as_os_str().is_empty()) {
+                    if !plan.exists(dir) {
+                        plan.create(dir.to_path_buf(), true);
+                    }
+                }
+            }
+            Op::Touch(path) => {
+                check_parent(path, plan)?;
+                if plan.is_dir(path) {
+                    return Err(format!("{} is a directory", path.display()));
+                }
+                if !plan.exists(path) {
+                    plan.create(path.clone(), false);
+                }
+            }
+            Op::Copy(sources, dest) | Op::Move(sources, dest) => {
+                let moving = matches!(self, Op::Move(..));
+                for (source, target) in targets(sources, dest, plan)? {
+                    if !plan.exists(&source) {
+                        return Err(format!("{} does not exist", source.display()));
+                    }
+                    if plan.exists(&target) {
+                        return Err(format!("{} already exists", target.display()));
+                    }
+                    if target.starts_with(&source) {
+                        return Err(format!("can't put {} inside itself", source.display()));
+                    }
+                    check_parent(&target, plan)?;
+                    let dir = plan.is_dir(&source);
+                    if moving {
+                        plan.remove(&source);
+                    }
+                    plan.create(target, dir);
+                }
+            }
+            Op::Trash(paths) => {
+                for path in paths {
+                    if !plan.exists(path) {
+                        return Err(format!("{} does not exist", path.display()));
+                    }
+                    if path.parent().is_none() {
+                        return Err(format!("refusing to trash {}", path.display()));
+                    }
+                    plan.remove(path);
+                }
+            }
+            Op::Rename(path, name) => {
+                if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                    return Err(format!("invalid name: {}", name));
+                }
+                if !plan.exists(path) {
+                    return Err(format!("{} does not exist", path.display()));
+                }
+                let target = path.with_file_name(name);
+                if plan.exists(&target) {
+                    return Err(format!("{} already exists", target.display()));
+                }
+                let dir = plan.is_dir(path);
+                plan.remove(path);
+                plan.create(target, dir);
+            }
+            Op::Link(sources, dest, kind) => {
+                if !plan.is_dir(dest) {
+                    return Err(format!("{} is not a directory", dest.display()));
+                }
+                for (source, target) in targets(sources, dest, plan)? {
+                    if !plan.exists(&source) {
+                        return Err(format!("{} does not exist", source.display()));
+                    }
+                    if plan.exists(&target) {
+                        return Err(format!("{} already exists", target.display()));
+                    }
+                    if matches!(kind, LinkKind::Hard) && plan.is_dir(&source) {
+                        return Err(format!("can't hard link directory {}", source.display()));
+                    }
+                    plan.create(target, false);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run(&self) -> Result<(), String> {
+        self.check(&mut Plan::default())?;
+
+        match self {
+            Op::Mkdir(path) => fs::create_dir_all(path).map_err(|e| error(path, e)),
+            Op::Touch(path) => fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map(|_| ())
+                .map_err(|e| error(path, e)),
+            Op::Copy(sources, dest) => {
+                for (source, target) in targets(sources, dest, &Plan::default())? {
+                    copy(&source, &target)?;
+                }
+                Ok(())
+            }
+            Op::Move(sources, dest) => {
+                for (source, target) in targets(sources, dest, &Plan::default())? {
+                    move_path(&source, &target)?;
+                }
+                Ok(())
+            }
+            Op::Trash(paths) => paths.iter().try_for_each(|p| trash(p)),
+            Op::Rename(path, name) => {
+                fs::rename(path, path.with_file_name(name)).map_err(|e| error(path, e))
+            }
+            Op::Link(sources, dest, kind) => {
+                for (source, target) in targets(sources, dest, &Plan::default())? {
+                    let result = match kind {
+                        LinkKind::Absolute => symlink(&source, &target),
+                        LinkKind::Relative => symlink(links::relative_path(dest, &source), &target),
+                        LinkKind::Hard => fs::hard_link(&source, &target),
+                    };
+                    result.map_err(|e| error(&target, e))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn error(path: &Path, e: std::io::Error) -> String {
+    format!("{}: {}", path.display(), e)
+}
+
+fn check_parent(path: &Path, plan: &Plan) -> Result<(), String> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && !plan.is_dir(parent) => {
+            Err(format!("{} is not a directory", parent.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+// where each source ends up: inside `dest` when it is a dir, otherwise `dest`
+// itself, which only works for a single source and when `dest` doesn't end
+// with a '/'
+fn targets(
+    sources: &[PathBuf],
+    dest: &Path,
+    plan: &Plan,
+) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    if plan.is_dir(dest) {
+        sources
+            .iter()
+            .map(|s| match s.file_name() {
+                Some(name) => Ok((s.clone(), dest.join(name))),
+                None => Err(format!("can't copy or move {}", s.display())),
+            })
+            .collect()
+    } else if sources.len() == 1 && !dest.as_os_str().as_bytes().ends_with(b"/") {
+        Ok(vec![(sources[0].clone(), dest.to_path_buf())])
+    } else {
+        Err(format!("{} is not a directory", dest.display()))
+    }
+}
+
+// copies files, dirs (recursively) and symlinks (as links)
+fn copy(source: &Path, target: &Path) -> Result<(), String> {
+    let meta = source.symlink_metadata().map_err(|e| error(source, e))?;
+    if meta.file_type().is_symlink() {
+        let link = fs::read_link(source).map_err(|e| error(source, e))?;
+        symlink(link, target).map_err(|e| error(target, e))
+    } else if meta.is_dir() {
+        fs::create_dir(target).map_err(|e| error(target, e))?;
+        fs::set_permissions(target, meta.permissions()).map_err(|e| error(target, e))?;
+        for entry in fs::read_dir(source).map_err(|e| error(source, e))? {
+            let entry = entry.map_err(|e| error(source, e))?;
+            copy(&entry.path(), &target.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(source, target)
+            .map(|_| ())
+            .map_err(|e| error(source, e))
+    }
+}
+
+// renames when possible, copying and removing the source across filesystems
+fn move_path(source: &Path, target: &Path) -> Result<(), String> {
+    match fs::rename(source, target) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            copy(source, target)?;
+            remove(source)
+        }
+        Err(e) => Err(error(source, e)),
+    }
+}
+
+fn remove(path: &Path) -> Result<(), String> {
+    let meta = path.symlink_metadata().map_err(|e| error(path, e))?;
+    if meta.is_dir() {
+        fs::remove_dir_all(path).map_err(|e| error(path, e))
+    } else {
+        fs::remove_file(path).map_err(|e| error(path, e))
+    }
+}
+
+// the freedesktop.org trash in the user's data dir
+pub fn trash_dir() -> Result<PathBuf, String> {
+    env::var("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| dirs::home_dir().map(|h| h.join(".local/share")).ok_or(()))
+        .map(|data| data.join("Trash"))
+        .map_err(|_| String::from("can't find the trash directory"))
+}
+
+// moves a path into the trash
+fn trash(path: &Path) -> Result<(), String> {
+    let trash = trash_dir()?;
+    let files = trash.join("files");
+    let info = trash.join("info");
+    fs::create_dir_all(&files).map_err(|e| error(&files, e))?;
+    fs::create_dir_all(&info).map_err(|e| error(&info, e))?;
+
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().map_err(|e| error(path, e))?.join(path)
+    };
+    let name = absolute
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("refusing to trash {}", path.display()))?;
+
+    // pick a name that isn't in the trash yet
+    let mut trashed = name.clone();
+    let mut n = 1;
+    while files.join(&trashed).symlink_metadata().is_ok()
+        || info.join(trashed.clone() + ".trashinfo").exists()
+    {
+        n += 1;
+        trashed = format!("{}.{}", name, n);
+    }
+
+    let date = status::strftime(SystemTime::now(), c"%Y-%m-%dT%H:%M:%S");
+    let info_file = info.join(trashed.clone() + ".trashinfo");
+    fs::write(
+        &info_file,
+        format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            percent_encode(&absolute),
+            date
+        ),
+    )
+    .map_err(|e| error(&info_file, e))?;
+
+    move_path(&absolute, &files.join(&trashed)).inspect_err(|_| {
+        let _ = fs::remove_file(&info_file);
+    })
+}
+
+// escapes a path the way trashinfo files want it, like in a URL but keeping
+// the '/'s
+fn percent_encode(path: &Path) -> String {
+    let mut res = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~/".contains(&b) {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{:02X}", b));
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn trashinfo_paths_are_percent_encoded() {
+        assert_eq!(percent_encode(Path::new("/a/b-c_d.e~")), "/a/b-c_d.e~");
+        assert_eq!(
+            percent_encode(Path::new("/tmp/a b%.txt")),
+            "/tmp/a%20b%25.txt"
+        );
+        assert_eq!(percent_encode(Path::new("/é")), "/%C3%A9");
+    }
+
+    #[test]
+    fn destinations_ending_with_a_slash_must_be_dirs() {
+        let dir = utils::TestDir::new();
+        let file = dir.write("y", "y");
+        let op = Op::Move(vec![file.clone()], dir.join("nope/"));
+        let err = op.check(&mut Plan::default()).unwrap_err();
+        assert!(err.ends_with("is not a directory"), "{}", err);
+        assert!(op.run().is_err());
+        assert!(file.exists());
+
+        // without the slash it's a rename, with it into an existing dir
+        fs::create_dir(dir.join("sub")).unwrap();
+        Op::Move(vec![file], dir.join("sub/")).run().unwrap();
+        assert!(dir.join("sub/y").exists());
+        Op::Move(vec![dir.join("sub/y")], dir.join("z"))
+            .run()
+            .unwrap();
+        assert!(dir.join("z").exists());
+    }
+
+    #[test]
+    fn dry_runs_follow_earlier_commands() {
+        let dir = utils::TestDir::new();
+        let mut plan = Plan::default();
+        Op::Mkdir(dir.join("a")).check(&mut plan).unwrap();
+        Op::Touch(dir.join("a/f")).check(&mut plan).unwrap();
+        Op::Move(vec![dir.join("a/f")], dir.join("a/"))
+            .check(&mut plan)
+            .unwrap_err();
+        Op::Move(vec![dir.join("a")], dir.join("b/"))
+            .check(&mut plan)
+            .unwrap_err();
+        Op::Move(vec![dir.join("a")], dir.join("b"))
+            .check(&mut plan)
+            .unwrap();
+        Op::Touch(dir.join("b/g")).check(&mut plan).unwrap();
+        assert!(Op::Touch(dir.join("a/g")).check(&mut plan).is_err());
+        // nothing was touched
+        assert!(!dir.join("a").exists());
+    }
+
+    #[test]
+    fn trash_writes_the_info_file() {
+        let dir = utils::TestDir::new();
+        env::set_var("XDG_DATA_HOME", dir.join("data"));
+        let file = dir.write("a b.txt", "a");
+
+        trash(&file).unwrap();
+        assert!(!file.exists());
+        assert!(dir.join("data/Trash/files/a b.txt").exists());
+        let info = fs::read_to_string(dir.join("data/Trash/info/a b.txt.trashinfo")).unwrap();
+        let lines: Vec<&str> = info.lines().collect();
+        assert_eq!(lines[0], "[Trash Info]");
+        assert_eq!(lines[1], format!("Path={}", percent_encode(&file)));
+        let date = lines[2].strip_prefix("DeletionDate=").unwrap();
+        // like 2023-04-28T14:03:59
+        assert_eq!(date.len(), 19);
+        assert_eq!(&date[10..11], "T");
+    }
+}
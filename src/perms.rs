@@ -0,0 +1,419 @@
+use std::{
+    ffi::{CStr, CString},
+    fs, io, mem,
+    os::unix::fs::{lchown, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    ptr,
+};
+
+// what a recursive change applies to
+#[derive(Clone, Copy, PartialEq)]
+pub enum Only {
+    All,
+    Files,
+    Dirs,
+}
+
+impl Only {
+    pub fn name(self) -> &'static str {
+        match self {
+            Only::All => "all",
+            Only::Files => "files only",
+            Only::Dirs => "dirs only",
+        }
+    }
+
+    fn next(self) -> Only {
+        match self {
+            Only::All => Only::Files,
+            Only::Files => Only::Dirs,
+            Only::Dirs => Only::All,
+        }
+    }
+
+    fn matches(self, meta: &fs::Metadata) -> bool {
+        match self {
+            Only::All => true,
+            Only::Files => !meta.is_dir(),
+            Only::Dirs => meta.is_dir(),
+        }
+    }
+}
+
+// the parts of the editor that can have focus, Bit(0) is the owner's read
+// bit and Bit(8) the others' execute bit
+#[derive(Clone, Copy, PartialEq)]
+pub enum Field {
+    Bit(u32),
+    Octal,
+    Owner,
+    Group,
+    Recursive,
+    Only,
+}
+
+// mode, owner and group of the selected entries, edited in a popup and
+// applied to all of them at once
+pub struct PermsEditor {
+    pub paths: Vec<PathBuf>,
+    // permission bits including setuid, setgid and sticky
+    pub mode: u32,
+    pub octal: String,
+    pub owner: String,
+    pub group: String,
+    // only what was changed is applied: the bits flipped from the first
+    // path's mode, or the whole mode once one is typed in
+    initial_mode: u32,
+    absolute: bool,
+    initial_owner: String,
+    initial_group: String,
+    pub recursive: bool,
+    pub only: Only,
+    pub has_dirs: bool,
+    focus: usize,
+    // typing into a text field replaces it until something is typed or
+    // deleted
+    fresh: bool,
+}
+
+impl PermsEditor {
+    // starts out with the mode and owners of the first path
+    pub fn open(paths: Vec<PathBuf>) -> io::Result<PermsEditor> {
+        let meta = fs::symlink_metadata(&paths[0])?;
+        let has_dirs = paths.iter().any(|p| p.is_dir() && !p.is_symlink());
+        let mode = meta.mode() & 0o7777;
+        let owner = user_name(meta.uid());
+        let group = group_name(meta.gid());
+        Ok(PermsEditor {
+            paths,
+            mode,
+            octal: format!("{:04o}", mode),
+            initial_mode: mode,
+            absolute: false,
+            initial_owner: owner.clone(),
+            initial_group: group.clone(),
+            owner,
+            group,
+            recursive: false,
+            only: Only::All,
+            has_dirs,
+            focus: 0,
+            fresh: true,
+        })
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        let mut fields: Vec<Field> = (0..9).map(Field::Bit).collect();
+        fields.extend([Field::Octal, Field::Owner, Field::Group]);
+        // recursion only makes sense with a dir in the selection
+        if self.has_dirs {
+            fields.extend([Field::Recursive, Field::Only]);
+        }
+        fields
+    }
+
+    pub fn field(&self) -> Field {
+        self.fields()[self.focus]
+    }
+
+    // moves the focus by `delta`, wrapping around
+    pub fn move_focus(&mut self, delta: isize) {
+        let count = self.fields().len() as isize;
+        self.focus = (self.focus as isize + delta).rem_euclid(count) as usize;
+        self.octal = format!("{:04o}", self.mode);
+        self.fresh = true;
+    }
+
+    // flips the focused bit or option
+    pub fn toggle(&mut self) {
+        match self.field() {
+            Field::Bit(i) => {
+                self.mode ^= 0o400 >> i;
+                self.octal = format!("{:04o}", self.mode);
+            }
+            Field::Recursive => self.recursive = !self.recursive,
+            Field::Only => self.only = self.only.next(),
+            _ => {}
+        }
+    }
+
+    pub fn type_char(&mut self, c: char) {
+        let text = match self.field() {
+            Field::Octal if c.is_digit(8) => &mut self.octal,
+            Field::Owner => &mut self.owner,
+            Field::Group => &mut self.group,
+            Field::Bit(_) | Field::Recursive | Field::Only if c == ' ' => {
+                return self.toggle();
+            }
+            _ => return,
+        };
+        if self.fresh {
+            text.clear();
+            self.fresh = false;
+        }
+        text.push(c);
+        self.parse_octal();
+    }
+
+    pub fn backspace(&mut self) {
+        let text = match self.field() {
+            Field::Octal => &mut self.octal,
+            Field::Owner => &mut self.owner,
+            Field::Group => &mut self.group,
+            _ => return,
+        };
+        text.pop();
+        self.fresh = false;
+        self.parse_octal();
+    }
+
+    // takes the typed octal mode once it has at least 3 digits
+    fn parse_octal(&mut self) {
+        if self.field() != Field::Octal || self.octal.len() < 3 || self.octal.len() > 4 {
+            return;
+        }
+        if let Ok(mode) = u32::from_str_radix(&self.octal, 8) {
+            self.mode = mode;
+            self.absolute = true;
+        }
+    }
+
+    // applies the changes to every path, and to everything under the dirs
+    // when recursive, returning what failed on each path
+    pub fn apply(&self) -> Result<Vec<(PathBuf, io::Error)>, String> {
+        let uid = if self.owner != self.initial_owner {
+            Some(user_id(&self.owner).ok_or_else(|| format!("no user named {}", self.owner))?)
+        } else {
+            None
+        };
+        let gid = if self.group != self.initial_group {
+            Some(group_id(&self.group).ok_or_else(|| format!("no group named {}", self.group))?)
+        } else {
+            None
+        };
+
+        let mut errors = vec![];
+        for path in &self.paths {
+            self.apply_to(path, uid, gid, &mut errors);
+        }
+        Ok(errors)
+    }
+
+    // bits to set and to clear on each path
+    fn masks(&self) -> (u32, u32) {
+        if self.absolute {
+            (self.mode, 0o7777 & !self.mode)
+        } else {
+            (
+                self.mode & !self.initial_mode,
+                self.initial_mode & !self.mode,
+            )
+        }
+    }
+
+    fn apply_to(
+        &self,
+        path: &Path,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        errors: &mut Vec<(PathBuf, io::Error)>,
+    ) {
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(e) => return errors.push((path.to_path_buf(), e)),
+        };
+
+        if !self.recursive || self.only.matches(&meta) {
+            // the mode of a symlink can't be changed, chmod would follow it
+            let (set, clear) = self.masks();
+            let mode = (meta.mode() & !clear) | set;
+            if mode != meta.mode() && !meta.file_type().is_symlink() {
+                if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+                    errors.push((path.to_path_buf(), e));
+                }
+            }
+            if uid.is_some() || gid.is_some() {
+                if let Err(e) = lchown(path, uid, gid) {
+                    errors.push((path.to_path_buf(), e));
+                }
+            }
+        }
+
+        if self.recursive && meta.is_dir() {
+            match fs::read_dir(path) {
+                Ok(entries) => {
+                    for entry in entries.flatten() {
+                        self.apply_to(&entry.path(), uid, gid, errors);
+                    }
+                }
+                Err(e) => errors.push((path.to_path_buf(), e)),
+            }
+        }
+    }
+}
+
+pub fn user_name(uid: u32) -> String {
+    lookup(
+        // SAFETY: every pointer is valid for the length of the call
+        |pw, buf, len, res| unsafe { libc::getpwuid_r(uid, pw, buf, len, res) },
+        // SAFETY: the name points into the buffer, which is still around
+        |pw: &libc::passwd| {
+            unsafe { CStr::from_ptr(pw.pw_name) }
+                .to_string_lossy()
+                .into_owned()
+        },
+    )
+    .unwrap_or_else(|| uid.to_string())
+}
+
+pub fn group_name(gid: u32) -> String {
+    lookup(
+        // SAFETY: as in user_name
+        |gr, buf, len, res| unsafe { libc::getgrgid_r(gid, gr, buf, len, res) },
+        // SAFETY: as in user_name
+        |gr: &libc::group| {
+            unsafe { CStr::from_ptr(gr.gr_name) }
+                .to_string_lossy()
+                .into_owned()
+        },
+    )
+    .unwrap_or_else(|| gid.to_string())
+}
+
+// a user name or numeric id
+fn user_id(name: &str) -> Option<u32> {
+    if let Ok(uid) = name.parse() {
+        return Some(uid);
+    }
+    let name = CString::new(name).ok()?;
+    lookup(
+        // SAFETY: as in user_name
+        |pw, buf, len, res| unsafe { libc::getpwnam_r(name.as_ptr(), pw, buf, len, res) },
+        |pw: &libc::passwd| pw.pw_uid,
+    )
+}
+
+// a group name or numeric id
+fn group_id(name: &str) -> Option<u32> {
+    if let Ok(gid) = name.parse() {
+        return Some(gid);
+    }
+    let name = CString::new(name).ok()?;
+    lookup(
+        // SAFETY: as in user_name
+        |gr, buf, len, res| unsafe { libc::getgrnam_r(name.as_ptr(), gr, buf, len, res) },
+        |gr: &libc::group| gr.gr_gid,
+    )
+}
+
+// runs one of the reentrant passwd or group lookups, which are safe from any
+// thread, growing the buffer for the entry's strings until they fit. None
+// when there is no such entry
+fn lookup<T, R>(
+    call: impl Fn(*mut T, *mut libc::c_char, libc::size_t, *mut *mut T) -> libc::c_int,
+    read: impl FnOnce(&T) -> R,
+) -> Option<R> {
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        // SAFETY: only used with passwd and group, plain C structs for which
+        // all zeroes is a valid value
+        let mut entry: T = unsafe { mem::zeroed() };
+        let mut found = ptr::null_mut();
+        match call(&mut entry, buf.as_mut_ptr(), buf.len(), &mut found) {
+            libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            0 if !found.is_null() => return Some(read(&entry)),
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    fn file(dir: &Path, name: &str, mode: u32) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().mode() & 0o7777
+    }
+
+    #[test]
+    fn toggles_only_change_their_bits() {
+        let dir = utils::TestDir::new();
+        let a = file(&dir, "a", 0o644);
+        let b = file(&dir, "b", 0o700);
+
+        let mut editor = PermsEditor::open(vec![a.clone(), b.clone()]).unwrap();
+        // group read off, others execute on
+        editor.move_focus(3);
+        editor.toggle();
+        editor.move_focus(5);
+        editor.toggle();
+        assert!(editor.apply().unwrap().is_empty());
+        assert_eq!(mode(&a), 0o605);
+        assert_eq!(mode(&b), 0o701);
+    }
+
+    #[test]
+    fn typed_modes_are_applied_as_is() {
+        let dir = utils::TestDir::new();
+        let a = file(&dir, "a", 0o644);
+        let b = file(&dir, "b", 0o700);
+
+        let mut editor = PermsEditor::open(vec![a.clone(), b.clone()]).unwrap();
+        editor.move_focus(9);
+        assert!(editor.field() == Field::Octal);
+        for c in "750".chars() {
+            editor.type_char(c);
+        }
+        assert!(editor.apply().unwrap().is_empty());
+        assert_eq!(mode(&a), 0o750);
+        assert_eq!(mode(&b), 0o750);
+    }
+
+    #[test]
+    fn nothing_changed_leaves_modes_alone() {
+        let dir = utils::TestDir::new();
+        let a = file(&dir, "a", 0o644);
+        let b = file(&dir, "b", 0o700);
+
+        let mut editor = PermsEditor::open(vec![a.clone(), b.clone()]).unwrap();
+        // flipped twice is back to how it was
+        editor.toggle();
+        editor.toggle();
+        assert!(editor.apply().unwrap().is_empty());
+        assert_eq!(mode(&a), 0o644);
+        assert_eq!(mode(&b), 0o700);
+    }
+
+    #[test]
+    fn names_and_ids_round_trip_from_any_thread() {
+        let lookups: Vec<_> = (0..8)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    (
+                        user_name(0),
+                        group_name(0),
+                        user_id("root"),
+                        group_id("root"),
+                    )
+                })
+            })
+            .collect();
+        for lookup in lookups {
+            let (user, group, uid, gid) = lookup.join().unwrap();
+            assert_eq!(user, "root");
+            assert_eq!(group, "root");
+            assert_eq!((uid, gid), (Some(0), Some(0)));
+        }
+        assert_eq!(user_name(4_000_000_000), "4000000000");
+        assert_eq!(user_id("no such user"), None);
+        assert_eq!(user_id("42"), Some(42));
+    }
+}
@@ -0,0 +1,407 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    path::Path,
+    process::{Command, Output, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{commands, error::Error, settings::Plugin, state::App};
+
+// how long a plugin gets to answer before it is killed
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// what kfm is asking the plugin for
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestKind {
+    Preview,
+    Columns,
+    Action,
+}
+
+// written to the plugin's stdin as a single line of JSON
+#[derive(Serialize)]
+pub struct Request<'a> {
+    pub kind: RequestKind,
+    pub cwd: &'a str,
+    pub selected: Option<&'a str>,
+    pub marked: Vec<&'a str>,
+    pub entries: &'a [String],
+}
+
+// read from the plugin's stdout, every field is optional so a plugin only
+// answers what it was asked for
+#[derive(Deserialize, Default)]
+pub struct Response {
+    pub preview: Option<String>,
+    // entry name -> column text
+    pub columns: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+}
+
+// things a plugin can ask kfm to do, applied in order after validation
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Action {
+    Cd { path: String },
+    Select { name: String },
+    Mark { path: String },
+    Unmark { path: String },
+    Message { text: String },
+    Refresh,
+}
+
+// spawns the plugin, sends it the request and waits for its response
+pub fn call(plugin: &Plugin, request: &Request) -> Result<Response, String> {
+    let input = serde_json::to_string(request).map_err(|e| e.to_string())? + "\n";
+
+    let mut child = Command::new(&plugin.exec)
+        .args(&plugin.args)
+        .current_dir(request.cwd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to start {}: {}", plugin.exec, e))?;
+
+    // read both pipes on their own threads so a chatty plugin can't block on
+    // a full pipe while we wait for it to exit, and write the request from
+    // another one so a plugin that talks before it reads can't block us
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+    if let Some(mut stdin) = child.stdin.take() {
+        thread::spawn(move || {
+            // a plugin that doesn't read its input is not an error
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if start.elapsed() > TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} timed out", plugin.exec));
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(5)),
+            Err(e) => return Err(format!("{} failed: {}", plugin.exec, e)),
+        }
+    };
+    // something the plugin started in the background can keep the pipes open
+    // after it exits
+    let left = TIMEOUT.saturating_sub(start.elapsed());
+    let output = Output {
+        status,
+        stdout: stdout
+            .recv_timeout(left)
+            .map_err(|_| format!("{} timed out", plugin.exec))?,
+        stderr: stderr.recv_timeout(left).unwrap_or_default(),
+    };
+
+    if !output.status.success() {
+        return Err(format!(
+            "{} exited with {}: {}",
+            plugin.exec,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        return Ok(Response::default());
+    }
+    serde_json::from_str(&stdout).map_err(|e| format!("{} sent invalid JSON: {}", plugin.exec, e))
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        let _ = tx.send(buf);
+    });
+    rx
+}
+
+// what a request is made of, owned so it can be sent from another thread
+struct Snapshot {
+    cwd: String,
+    selected: Option<String>,
+    marked: Vec<String>,
+    entries: Vec<String>,
+}
+
+impl Snapshot {
+    fn of(app: &mut App) -> Snapshot {
+        let cwd = app.working_dir();
+        Snapshot {
+            selected: app.selected_name().map(|f| cwd.clone() + "/" + f),
+            marked: app.marked.iter().cloned().collect(),
+            entries: app.entry_names(),
+            cwd,
+        }
+    }
+
+    fn request(&self, kind: RequestKind) -> Request<'_> {
+        Request {
+            kind,
+            cwd: &self.cwd,
+            selected: self.selected.as_deref(),
+            marked: self.marked.iter().map(|m| m.as_str()).collect(),
+            entries: &self.entries,
+        }
+    }
+}
+
+// calls the plugin with a request built from the app on a background thread,
+// what `then` makes of the response arrives on the returned channel
+pub fn spawn<T: Send + 'static>(
+    plugin: Plugin,
+    kind: RequestKind,
+    app: &mut App,
+    then: impl FnOnce(Result<Response, String>) -> T + Send + 'static,
+) -> Receiver<T> {
+    let snapshot = Snapshot::of(app);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // nobody is waiting when the preview or action was superseded
+        let _ = tx.send(then(call(&plugin, &snapshot.request(kind))));
+    });
+    rx
+}
+
+// first plugin configured to preview files with the extension of `path`
+pub fn previewer_for<'a>(
+    plugins: impl Iterator<Item = &'a Plugin>,
+    path: &str,
+) -> Option<&'a Plugin> {
+    let extension = Path::new(path).extension()?.to_str()?;
+    plugins
+        .into_iter()
+        .find(|p| p.preview.iter().any(|e| e == extension))
+}
+
+// asks every column provider about the current listing on a background
+// thread, their merged columns arrive on the returned channel. None when no
+// plugin adds a column
+pub fn columns(app: &mut App) -> Option<Receiver<HashMap<String, String>>> {
+    let providers: Vec<Plugin> = app
+        .settings
+        .plugins
+        .values()
+        .filter(|p| p.column)
+        .cloned()
+        .collect();
+    if providers.is_empty() {
+        return None;
+    }
+
+    let snapshot = Snapshot::of(app);
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let request = snapshot.request(RequestKind::Columns);
+        let mut columns: HashMap<String, String> = HashMap::new();
+        for plugin in providers {
+            // a broken column provider shouldn't stop the others
+            if let Ok(Response {
+                columns: Some(c), ..
+            }) = call(&plugin, &request)
+            {
+                for (entry, text) in c {
+                    columns
+                        .entry(entry)
+                        .and_modify(|t| {
+                            t.push(' ');
+                            t.push_str(&text)
+                        })
+                        .or_insert(text);
+                }
+            }
+        }
+        // the listing changed before the columns were ready
+        let _ = tx.send(columns);
+    });
+    Some(rx)
+}
+
+// checks each action against the filesystem and the listing before applying
+// it, showing plugin messages in the preview popup and reporting rejected
+// actions
+pub fn apply(actions: Vec<Action>, app: &mut App, hiding_dot_files: bool) {
+    let mut errors = vec![];
+    let mut messages = vec![];
+
+    for action in actions {
+        match action {
+            Action::Cd { path } => {
+                let path = resolve(app, &path);
+                if !Path::new(&path).is_dir() {
+                    errors.push(format!("cd: {} is not a directory", path));
+                } else if let Err(e) =
+                    commands::enter_dir(path.clone(), app, "./", hiding_dot_files)
+                {
+                    errors.push(format!("cd: {}", e));
+                }
+            }
+            Action::Select { name } => {
+                if !app.select(&name) {
+                    errors.push(format!("select: no entry named {}", name));
+                }
+            }
+            Action::Mark { path } => {
+                let path = resolve(app, &path);
+                if Path::new(&path).exists() {
+                    app.marked.insert(path);
+                } else {
+                    errors.push(format!("mark: {} does not exist", path));
+                }
+            }
+            Action::Unmark { path } => {
+                app.marked.remove(&resolve(app, &path));
+            }
+            Action::Message { text } => messages.push(text),
+            Action::Refresh => app.new_cwd("./", hiding_dot_files),
+        }
+    }
+
+    for e in errors {
+        app.report(Error::Plugin(e));
+    }
+    if !messages.is_empty() {
+        app.file_cont = messages.join("\n");
+        app.prev = true;
+    }
+}
+
+// plugins may send paths relative to the current dir
+fn resolve(app: &App, path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        path.to_string()
+    } else {
+        app.working_dir() + "/" + path
+    }
+}
+
+// starts the action plugin with the given name, what it returns is applied
+// by App::poll_background once it is in
+pub fn run_action(name: &str, app: &mut App, hiding_dot_files: bool) {
+    let plugin = match app.settings.plugins.get(name) {
+        Some(p) => p.clone(),
+        None => return,
+    };
+
+    let results = spawn(plugin, RequestKind::Action, app, move |response| {
+        (response, hiding_dot_files)
+    });
+    app.action_results = Some(results);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{settings::Settings, utils};
+
+    fn shell_plugin(script: &str) -> Plugin {
+        Plugin {
+            exec: String::from("sh"),
+            args: vec![String::from("-c"), String::from(script)],
+            preview: vec![],
+            column: false,
+            key: None,
+        }
+    }
+
+    fn request(entries: &[String]) -> Request<'_> {
+        Request {
+            kind: RequestKind::Preview,
+            cwd: "/",
+            selected: None,
+            marked: vec![],
+            entries,
+        }
+    }
+
+    #[test]
+    fn reads_the_response() {
+        let plugin = shell_plugin(r#"read -r _; echo '{"preview": "hello"}'"#);
+        let response = call(&plugin, &request(&[])).unwrap();
+        assert_eq!(response.preview.as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn talking_before_reading_does_not_block() {
+        // both the request and the output are bigger than a pipe buffer
+        let entries: Vec<String> = (0..50_000).map(|i| format!("entry-{}", i)).collect();
+        let plugin =
+            shell_plugin("head -c 200000 /dev/zero | tr '\\0' ' '; cat >/dev/null; echo {}");
+        let response = call(&plugin, &request(&entries)).unwrap();
+        assert!(response.preview.is_none());
+    }
+
+    #[test]
+    fn pipes_held_open_time_out() {
+        let plugin = shell_plugin("sleep 10 & echo {}");
+        let start = Instant::now();
+        let err = call(&plugin, &request(&[])).err().unwrap();
+        assert!(err.contains("timed out"), "{}", err);
+        assert!(start.elapsed() < TIMEOUT + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn previewer_is_picked_by_extension() {
+        let mut images = shell_plugin("true");
+        images.preview = vec![String::from("png")];
+        let plugins = [images];
+        assert!(previewer_for(plugins.iter(), "/a/b.png").is_some());
+        assert!(previewer_for(plugins.iter(), "/a/b.txt").is_none());
+        assert!(previewer_for(plugins.iter(), "/a/png").is_none());
+    }
+
+    // polls the app until `done` or the plugin timeout
+    fn wait(app: &mut App, done: impl Fn(&App) -> bool) {
+        let start = Instant::now();
+        while !done(app) && start.elapsed() < TIMEOUT {
+            app.poll_background();
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn previews_and_actions_do_not_block() {
+        let dir = utils::TestDir::new();
+        dir.write("a.png", "");
+        dir.write("b", "");
+        let mut preview = shell_plugin(r#"sleep 0.5; echo '{"preview": "an image"}'"#);
+        preview.preview = vec![String::from("png")];
+        let action =
+            shell_plugin(r#"sleep 0.5; echo '{"actions": [{"type": "select", "name": "b"}]}'"#);
+        let mut settings = Settings::default();
+        settings.plugins.insert(String::from("preview"), preview);
+        settings.plugins.insert(String::from("action"), action);
+        let mut app = App::new(settings, dir.to_path_buf());
+
+        let start = Instant::now();
+        assert_eq!(commands::preview("a.png", &mut app), "waiting for sh...");
+        app.prev = true;
+        run_action("action", &mut app, true);
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        wait(&mut app, |app| {
+            app.file_cont == "an image" && app.action_results.is_none()
+        });
+        assert_eq!(app.file_cont, "an image");
+        assert_eq!(app.selected_name(), Some("b"));
+    }
+}
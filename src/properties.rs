@@ -0,0 +1,302 @@
+use std::{
+    fs::{self, File, FileType, Metadata},
+    io::Read,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{perms, sizes::DirSizes, status};
+
+// bytes read to detect the mime type
+const SNIFF_BYTES: u64 = 8192;
+
+// symlinks followed before giving up on a chain, like the kernel's limit
+const MAX_LINKS: usize = 40;
+
+// label and value rows describing `path`, read straight from its metadata
+pub fn read(path: &Path, sizes: &DirSizes) -> Vec<(&'static str, String)> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) => return vec![("error", e.to_string())],
+    };
+
+    let mut rows = vec![
+        ("path", path.display().to_string()),
+        ("type", kind(&meta.file_type()).to_string()),
+        (
+            "size",
+            format!("{} bytes ({})", meta.len(), status::human_size(meta.len())),
+        ),
+    ];
+    if let Some(total) = sizes.get(path) {
+        rows.push(("total size", status::human_size(total)));
+    }
+    rows.extend([
+        ("inode", meta.ino().to_string()),
+        ("hard links", meta.nlink().to_string()),
+        (
+            "permissions",
+            format!(
+                "{} ({:04o})",
+                status::permissions(&meta),
+                meta.mode() & 0o7777
+            ),
+        ),
+        (
+            "owner",
+            format!("{} ({})", perms::user_name(meta.uid()), meta.uid()),
+        ),
+        (
+            "group",
+            format!("{} ({})", perms::group_name(meta.gid()), meta.gid()),
+        ),
+        ("modified", time(meta.modified().ok())),
+        ("accessed", time(meta.accessed().ok())),
+        (
+            "changed",
+            time(since_epoch(meta.ctime(), meta.ctime_nsec())),
+        ),
+        ("created", time(meta.created().ok())),
+        ("mime type", mime_type(path)),
+    ]);
+
+    if meta.file_type().is_symlink() {
+        rows.push(("link chain", link_chain(path)));
+    }
+    // counts for dirs and links to dirs
+    if path.is_dir() {
+        rows.push(("entries", entry_counts(path)));
+    }
+    rows
+}
+
+fn kind(file_type: &FileType) -> &'static str {
+    if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_file() {
+        "file"
+    } else if file_type.is_fifo() {
+        "fifo"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_char_device() {
+        "character device"
+    } else if file_type.is_block_device() {
+        "block device"
+    } else {
+        "unknown"
+    }
+}
+
+fn since_epoch(secs: i64, nanos: i64) -> Option<SystemTime> {
+    let secs = u64::try_from(secs).ok()?;
+    UNIX_EPOCH.checked_add(Duration::new(secs, nanos as u32))
+}
+
+fn time(time: Option<SystemTime>) -> String {
+    match time {
+        Some(time) => status::format_time(time),
+        None => String::from("unknown"),
+    }
+}
+
+// sniffed from the first bytes of the file (following symlinks), files
+// without a known signature are text when they are valid utf-8
+fn mime_type(path: &Path) -> String {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return String::from("inode/symlink"),
+    };
+    if meta.is_dir() {
+        return String::from("inode/directory");
+    }
+    if !meta.is_file() {
+        return String::from("inode/x-special");
+    }
+    if meta.len() == 0 {
+        return String::from("inode/x-empty");
+    }
+
+    let mut buf = vec![];
+    if let Err(e) = File::open(path).and_then(|f| f.take(SNIFF_BYTES).read_to_end(&mut buf)) {
+        return e.to_string();
+    }
+    if let Some(kind) = infer::get(&buf) {
+        return kind.mime_type().to_string();
+    }
+    // the read may have cut a multibyte character in two
+    let text = match std::str::from_utf8(&buf) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    if text && !buf.contains(&0) {
+        String::from("text/plain")
+    } else {
+        String::from("application/octet-stream")
+    }
+}
+
+// path -> target -> target's target ..., until something that isn't a link
+fn link_chain(path: &Path) -> String {
+    let mut chain = vec![path.display().to_string()];
+    let mut current = path.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        let target = match fs::read_link(&current) {
+            Ok(target) => target,
+            Err(_) => return chain.join(" -> "),
+        };
+        chain.push(target.display().to_string());
+        let dir = current.parent().map(Path::to_path_buf).unwrap_or_default();
+        current = dir.join(target);
+        if fs::symlink_metadata(&current).is_err() {
+            chain.push(String::from("(missing)"));
+            return chain.join(" -> ");
+        }
+    }
+    chain.push(String::from("(too many links)"));
+    chain.join(" -> ")
+}
+
+// entries directly in `dir` by type
+fn entry_counts(dir: &Path) -> String {
+    let entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.flatten().map(|e| e.path()).collect(),
+        Err(e) => return e.to_string(),
+    };
+    let metas: Vec<Metadata> = entries
+        .iter()
+        .filter_map(|e| fs::symlink_metadata(e).ok())
+        .collect();
+    let dirs = metas.iter().filter(|m| m.is_dir()).count();
+    let links = metas.iter().filter(|m| m.file_type().is_symlink()).count();
+    let hidden = entries
+        .iter()
+        .filter(|e| {
+            e.file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+        })
+        .count();
+    format!(
+        "{} ({} files, {} dirs, {} links, {} hidden)",
+        entries.len(),
+        metas.len() - dirs - links,
+        dirs,
+        links,
+        hidden
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        os::unix::fs::{symlink, PermissionsExt},
+    };
+
+    use super::*;
+    use crate::utils::TestDir;
+
+    fn rows(path: &Path) -> HashMap<&'static str, String> {
+        read(path, &DirSizes::spawn()).into_iter().collect()
+    }
+
+    #[test]
+    fn files() {
+        let dir = TestDir::new();
+        let file = dir.write("a.txt", "hello");
+        fs::hard_link(&file, dir.join("b.txt")).unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+
+        let rows = rows(&file);
+        assert_eq!(rows["path"], file.display().to_string());
+        assert_eq!(rows["type"], "file");
+        assert_eq!(rows["size"], "5 bytes (5B)");
+        assert_eq!(rows["hard links"], "2");
+        assert_eq!(
+            rows["inode"],
+            fs::metadata(&file).unwrap().ino().to_string()
+        );
+        assert_eq!(rows["permissions"], "-rw-r----- (0640)");
+        let uid = fs::metadata(&file).unwrap().uid();
+        assert_eq!(
+            rows["owner"],
+            format!("{} ({})", perms::user_name(uid), uid)
+        );
+        assert_eq!(rows["mime type"], "text/plain");
+        assert!(!rows.contains_key("link chain") && !rows.contains_key("entries"));
+    }
+
+    #[test]
+    fn mime_types_come_from_the_contents() {
+        let dir = TestDir::new();
+        let png = dir.write("image.txt", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+        assert_eq!(mime_type(&png), "image/png");
+        assert_eq!(
+            mime_type(&dir.write("bin", [1, 0, 2])),
+            "application/octet-stream"
+        );
+        assert_eq!(mime_type(&dir.write("empty", "")), "inode/x-empty");
+        assert_eq!(mime_type(&dir), "inode/directory");
+
+        // a multibyte character cut off by the sniffed bytes is still text
+        let mut text = "a".repeat(SNIFF_BYTES as usize - 1).into_bytes();
+        text.extend("é".as_bytes());
+        assert_eq!(mime_type(&dir.write("cut", text)), "text/plain");
+    }
+
+    #[test]
+    fn dirs_count_their_entries() {
+        let dir = TestDir::new();
+        dir.write("a", "");
+        dir.write(".b", "");
+        dir.write("sub/c", "");
+        symlink("a", dir.join("link")).unwrap();
+
+        let rows = rows(&dir);
+        assert_eq!(rows["type"], "directory");
+        assert_eq!(rows["entries"], "4 (2 files, 1 dirs, 1 links, 1 hidden)");
+        assert!(!rows.contains_key("total size"));
+    }
+
+    #[test]
+    fn link_chains() {
+        let dir = TestDir::new();
+        dir.write("target", "");
+        symlink("target", dir.join("one")).unwrap();
+        symlink(dir.join("one"), dir.join("two")).unwrap();
+        symlink("nowhere", dir.join("broken")).unwrap();
+        symlink("loop", dir.join("loop")).unwrap();
+
+        let two = dir.join("two");
+        let rows = rows(&two);
+        assert_eq!(rows["type"], "symlink");
+        assert_eq!(
+            rows["link chain"],
+            format!(
+                "{} -> {} -> target",
+                two.display(),
+                dir.join("one").display()
+            )
+        );
+        assert_eq!(rows["mime type"], "inode/x-empty");
+
+        let broken = dir.join("broken");
+        assert_eq!(
+            link_chain(&broken),
+            format!("{} -> nowhere -> (missing)", broken.display())
+        );
+        assert_eq!(mime_type(&broken), "inode/symlink");
+        assert!(link_chain(&dir.join("loop")).ends_with("loop -> (too many links)"));
+    }
+
+    #[test]
+    fn missing_paths() {
+        let dir = TestDir::new();
+        let rows = read(&dir.join("nope"), &DirSizes::spawn());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, "error");
+    }
+}
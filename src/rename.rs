@@ -0,0 +1,242 @@
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+
+use crate::utils;
+
+// bulk renaming like vidir: writes the names of `paths` to a temp file, opens
+// `editor` on it and reads the edited names back, one per line in the same
+// order
+pub fn edit_names(paths: &[PathBuf], editor: &str) -> Result<Vec<String>, String> {
+    let mut names = vec![];
+    for path in paths {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .ok_or_else(|| format!("can't rename {}", path.display()))?;
+        if name.contains('\n') {
+            return Err(format!("can't edit a name with a newline: {:?}", name));
+        }
+        names.push(name);
+    }
+
+    // the file goes in a new dir only the user can get into, so nobody can
+    // put a file or symlink in its place
+    let dir = utils::temp_dir("kfm-rename-").map_err(|e| format!("temp dir: {}", e))?;
+    let file = dir.join("names.txt");
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&file)
+        .and_then(|mut f| f.write_all((names.join("\n") + "\n").as_bytes()));
+    if let Err(e) = written {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(format!("{}: {}", file.display(), e));
+    }
+    let status = Command::new(editor).arg(&file).status();
+    let edited = fs::read_to_string(&file);
+    let _ = fs::remove_dir_all(&dir);
+
+    match status {
+        Ok(s) if s.success() => {}
+        Ok(s) => return Err(format!("{} exited with {}, nothing renamed", editor, s)),
+        Err(e) => return Err(format!("failed to open {}: {}", editor, e)),
+    }
+    let edited = edited.map_err(|e| format!("{}: {}", file.display(), e))?;
+    Ok(edited.lines().map(String::from).collect())
+}
+
+// pairs each path with its new name, leaving out the unchanged ones. Refuses
+// lines being added or removed, invalid names, two entries getting the same
+// name and names taken by entries that aren't renamed away
+pub fn plan(paths: &[PathBuf], names: &[String]) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    if names.len() != paths.len() {
+        return Err(format!(
+            "expected {} names, got {}, nothing renamed",
+            paths.len(),
+            names.len()
+        ));
+    }
+
+    let mut renames = vec![];
+    for (path, name) in paths.iter().zip(names) {
+        if path.file_name().is_some_and(|n| n == name.as_str()) {
+            continue;
+        }
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(format!("invalid name: {:?}", name));
+        }
+        renames.push((path.clone(), path.with_file_name(name)));
+    }
+
+    let sources: HashSet<&Path> = renames.iter().map(|(s, _)| s.as_path()).collect();
+    let mut targets = HashSet::new();
+    for (_, target) in &renames {
+        if !targets.insert(target.as_path()) {
+            return Err(format!("{} would be used twice", target.display()));
+        }
+        if target.symlink_metadata().is_ok() && !sources.contains(target.as_path()) {
+            return Err(format!("{} already exists", target.display()));
+        }
+    }
+    Ok(renames)
+}
+
+// renames every source to a temp name first and then to its target, so swaps
+// and cycles work; if a step fails the ones done are undone
+pub fn apply(renames: &[(PathBuf, PathBuf)]) -> Result<(), String> {
+    let mut steps = vec![];
+    for (i, (source, _)) in renames.iter().enumerate() {
+        let temp = source.with_file_name(format!(".kfm-rename-{}-{}", process::id(), i));
+        steps.push((source.clone(), temp));
+    }
+    let second: Vec<(PathBuf, PathBuf)> = steps
+        .iter()
+        .zip(renames)
+        .map(|((_, temp), (_, target))| (temp.clone(), target.clone()))
+        .collect();
+    steps.extend(second);
+
+    for (done, (from, to)) in steps.iter().enumerate() {
+        // never clobber something that appeared since the plan was made
+        let result = if to.symlink_metadata().is_ok() {
+            Err(format!("{} already exists", to.display()))
+        } else {
+            fs::rename(from, to).map_err(|e| format!("{}: {}", from.display(), e))
+        };
+        if let Err(e) = result {
+            for (from, to) in steps[..done].iter().rev() {
+                let _ = fs::rename(to, from);
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::utils::TestDir;
+
+    // a temp dir holding the given files, each containing its own name
+    fn with_files(files: &[&str]) -> TestDir {
+        let dir = TestDir::new();
+        for file in files {
+            dir.write(file, file);
+        }
+        dir
+    }
+
+    fn paths(dir: &Path, names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(|n| dir.join(n)).collect()
+    }
+
+    // file name -> contents
+    fn contents(dir: &Path) -> Vec<(String, String)> {
+        let mut res: Vec<(String, String)> = fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|e| {
+                let name = e.file_name().to_string_lossy().into_owned();
+                (name, fs::read_to_string(e.path()).unwrap())
+            })
+            .collect();
+        res.sort();
+        res
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn pairs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn swaps() {
+        let dir = with_files(&["a", "b"]);
+        let renames = plan(&paths(&dir, &["a", "b"]), &names(&["b", "a"])).unwrap();
+        apply(&renames).unwrap();
+        assert_eq!(contents(&dir), pairs(&[("a", "b"), ("b", "a")]));
+    }
+
+    #[test]
+    fn cycles() {
+        let dir = with_files(&["a", "b", "c"]);
+        let renames = plan(&paths(&dir, &["a", "b", "c"]), &names(&["b", "c", "a"])).unwrap();
+        apply(&renames).unwrap();
+        assert_eq!(contents(&dir), pairs(&[("a", "c"), ("b", "a"), ("c", "b")]));
+    }
+
+    #[test]
+    fn unchanged_names_are_left_out() {
+        let dir = with_files(&["a", "b"]);
+        let renames = plan(&paths(&dir, &["a", "b"]), &names(&["a", "c"])).unwrap();
+        assert_eq!(renames, [(dir.join("b"), dir.join("c"))]);
+    }
+
+    #[test]
+    fn refuses_duplicate_targets() {
+        let dir = with_files(&["a", "b"]);
+        let err = plan(&paths(&dir, &["a", "b"]), &names(&["c", "c"])).unwrap_err();
+        assert!(err.contains("would be used twice"), "{}", err);
+    }
+
+    #[test]
+    fn refuses_existing_targets() {
+        let dir = with_files(&["a", "b"]);
+        let err = plan(&paths(&dir, &["a"]), &names(&["b"])).unwrap_err();
+        assert!(err.contains("already exists"), "{}", err);
+    }
+
+    #[test]
+    fn refuses_bad_names_and_counts() {
+        let dir = with_files(&["a", "b"]);
+        let paths = paths(&dir, &["a", "b"]);
+        assert!(plan(&paths, &names(&["a"])).is_err());
+        assert!(plan(&paths, &names(&["a", ""])).is_err());
+        assert!(plan(&paths, &names(&["a", "x/y"])).is_err());
+        assert!(plan(&paths, &names(&["a", ".."])).is_err());
+    }
+
+    #[test]
+    fn failures_roll_back() {
+        let dir = with_files(&["a", "b", "c"]);
+        let renames = plan(&paths(&dir, &["a", "b", "c"]), &names(&["b", "c", "d"])).unwrap();
+        // appears after the plan was made, the last rename fails on it
+        dir.write("d", "d");
+        let err = apply(&renames).unwrap_err();
+        assert!(err.contains("already exists"), "{}", err);
+        assert_eq!(
+            contents(&dir),
+            pairs(&[("a", "a"), ("b", "b"), ("c", "c"), ("d", "d")])
+        );
+    }
+
+    #[test]
+    fn edits_names_in_a_private_file() {
+        let dir = with_files(&["a", "b"]);
+        // an editor that checks the file's mode and uppercases the names
+        let editor = dir.write(
+            "editor",
+            "#!/bin/sh\n[ \"$(stat -c %a \"$1\")\" = 600 ] || exit 1\n\
+             tr a-z A-Z < \"$1\" > \"$1.new\" && mv \"$1.new\" \"$1\"\n",
+        );
+        fs::set_permissions(&editor, fs::Permissions::from_mode(0o700)).unwrap();
+
+        let edited = edit_names(&paths(&dir, &["a", "b"]), &editor.to_string_lossy()).unwrap();
+        assert_eq!(edited, ["A", "B"]);
+    }
+}
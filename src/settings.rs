@@ -1,26 +1,274 @@
 use config::Config;
-use dirs;
-use std::collections::HashMap;
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::path::Path;
 
-pub fn get_conf() -> HashMap<String, String> {
-    let home = match dirs::home_dir() {
-        None => panic!("no home dir found"),
-        Some(h) => h,
-    };
+use crate::status;
+
+// every character key kfm binds itself, a command or plugin on one of these
+// would never run
+const BUILTIN_KEYS: &str = " qjkltP|_aopnw[]123456789CsuX=rxcmv!SizUERMJLgf?:%dD";
+
+#[derive(Deserialize, Default)]
+pub struct Settings {
+    // program used to open files, defaults to vim
+    pub editor: Option<String>,
+    // tmux binary, defaults to the one on $PATH
+    pub tmux: Option<String>,
+    // pane or window the send to pane prompt starts with
+    pub tmux_target: Option<String>,
+    // show dot files
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub sort: Sort,
+    #[serde(default)]
+    pub layout: Layout,
+    // work out recursive dir sizes whenever a dir is listed
+    #[serde(default)]
+    pub dir_sizes: bool,
+    // format string of the status bar, see status.rs for the fields
+    pub status_format: Option<String>,
+    // user defined commands, keyed by name
+    #[serde(default)]
+    pub commands: BTreeMap<String, UserCommand>,
+    // external plugins, keyed by name
+    #[serde(default)]
+    pub plugins: BTreeMap<String, Plugin>,
+}
+
+impl Settings {
+    pub fn editor(&self) -> &str {
+        self.editor.as_deref().unwrap_or("vim")
+    }
+
+    pub fn status_format(&self) -> &str {
+        self.status_format
+            .as_deref()
+            .unwrap_or(status::DEFAULT_FORMAT)
+    }
+
+    // refuses keys taken by a built-in binding or bound twice
+    fn check_keys(&self) -> Result<(), String> {
+        let commands = self
+            .commands
+            .iter()
+            .map(|(name, c)| (format!("commands.{}", name), c.key));
+        let plugins = self
+            .plugins
+            .iter()
+            .map(|(name, p)| (format!("plugins.{}", name), p.key));
+
+        let mut bound: BTreeMap<char, String> = BTreeMap::new();
+        for (name, key) in commands.chain(plugins) {
+            let key = match key {
+                Some(key) => key,
+                None => continue,
+            };
+            if BUILTIN_KEYS.contains(key) {
+                return Err(format!("key '{}' of {} is a built-in key", key, name));
+            }
+            if let Some(other) = bound.insert(key, name.clone()) {
+                return Err(format!(
+                    "key '{}' is bound by both {} and {}",
+                    key, other, name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// order of the listing, dirs always come first
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    #[default]
+    Name,
+    Size,
+    Time,
+    Ext,
+}
+
+impl Sort {
+    pub fn from_name(name: &str) -> Option<Sort> {
+        match name {
+            "name" => Some(Sort::Name),
+            "size" => Some(Sort::Size),
+            "time" => Some(Sort::Time),
+            "ext" => Some(Sort::Ext),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sort::Name => "name",
+            Sort::Size => "size",
+            Sort::Time => "time",
+            Sort::Ext => "ext",
+        }
+    }
+
+    // ls flag for this order
+    pub fn ls_flag(&self) -> Option<&'static str> {
+        match self {
+            Sort::Name => None,
+            Sort::Size => Some("-S"),
+            Sort::Time => Some("-t"),
+            Sort::Ext => Some("-X"),
+        }
+    }
+}
 
-    let path = Path::new("").join(home).join(".config/kfm.toml");
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    // quick help above the listing
+    #[default]
+    Default,
+    // listing only
+    Compact,
+}
+
+impl Layout {
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "default" => Some(Layout::Default),
+            "compact" => Some(Layout::Compact),
+            _ => None,
+        }
+    }
+}
+
+// a `[commands.<name>]` entry in kfm.toml
+//
+// [commands.test]
+// run = "cargo test"
+// key = "T"
+// confirm = true
+// output = "interactive"
+#[derive(Deserialize, Clone)]
+pub struct UserCommand {
+    // shell template, supports the same placeholders as the `!` prompt
+    pub run: String,
+    pub key: Option<char>,
+    #[serde(default)]
+    pub confirm: bool,
+    #[serde(default)]
+    pub output: OutputMode,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    // show captured output in the preview popup
+    #[default]
+    Popup,
+    // suspend the tui and give the command the terminal
+    Interactive,
+    // discard output
+    Silent,
+}
 
-    let conf = Config::builder()
-        .add_source(config::File::with_name(path.to_str().unwrap()))
-        .build();
+// a `[plugins.<name>]` entry in kfm.toml, see plugins.rs for the protocol
+//
+// [plugins.images]
+// exec = "kfm-images"
+// preview = ["png", "jpg"]
+// column = false
+// key = "I"
+#[derive(Deserialize, Clone)]
+pub struct Plugin {
+    pub exec: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    // file extensions the plugin previews
+    #[serde(default)]
+    pub preview: Vec<String>,
+    // the plugin adds a column to the listing
+    #[serde(default)]
+    pub column: bool,
+    // runs the plugin as an action
+    pub key: Option<char>,
+}
 
-    let settings = match conf {
-        Err(_) => HashMap::<String, String>::new(),
-        Ok(s) => {
-            s.try_deserialize::<HashMap<String, String>>().unwrap()
+// loads ~/.config/kfm.toml, or `path` when given, a missing default config
+// is not an error
+pub fn get_conf(path: Option<&str>) -> Result<Settings, String> {
+    let path = match path {
+        Some(p) => {
+            if !Path::new(p).exists() {
+                return Err(format!("config file {} does not exist", p));
+            }
+            Path::new(p).to_path_buf()
+        }
+        None => match dirs::home_dir() {
+            None => return Ok(Settings::default()),
+            Some(home) => home.join(".config/kfm.toml"),
         },
     };
 
-    return settings;
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+
+    Config::builder()
+        .add_source(config::File::new(
+            &path.to_string_lossy(),
+            config::FileFormat::Toml,
+        ))
+        .build()
+        .and_then(|c| c.try_deserialize::<Settings>())
+        .map_err(|e| e.to_string())
+        .and_then(|s| s.check_keys().map(|_| s))
+        .map_err(|e| format!("invalid config {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(key: char) -> UserCommand {
+        UserCommand {
+            run: String::from("true"),
+            key: Some(key),
+            confirm: false,
+            output: OutputMode::Silent,
+        }
+    }
+
+    #[test]
+    fn free_keys_are_accepted() {
+        let mut settings = Settings::default();
+        settings.commands.insert(String::from("a"), command('T'));
+        settings.commands.insert(String::from("b"), command('Y'));
+        assert!(settings.check_keys().is_ok());
+    }
+
+    #[test]
+    fn builtin_keys_are_refused() {
+        let mut settings = Settings::default();
+        settings.commands.insert(String::from("test"), command('x'));
+        let err = settings.check_keys().unwrap_err();
+        assert!(err.contains("commands.test"), "{}", err);
+    }
+
+    #[test]
+    fn keys_bound_twice_are_refused() {
+        let mut settings = Settings::default();
+        settings.commands.insert(String::from("test"), command('T'));
+        settings.plugins.insert(
+            String::from("images"),
+            Plugin {
+                exec: String::from("kfm-images"),
+                args: vec![],
+                preview: vec![],
+                column: false,
+                key: Some('T'),
+            },
+        );
+        let err = settings.check_keys().unwrap_err();
+        assert!(err.contains("commands.test and plugins.images"), "{}", err);
+    }
 }
@@ -0,0 +1,246 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+// recursive dir sizes, worked out one scan at a time on a background thread
+// and cached by path, a scan caches every dir under the one asked for
+pub struct DirSizes {
+    cache: HashMap<PathBuf, u64>,
+    // asked for but not back yet
+    pending: HashSet<PathBuf>,
+    jobs: Sender<PathBuf>,
+    // the dir asked for and the size of every dir under it
+    results: Receiver<(PathBuf, Vec<(PathBuf, u64)>)>,
+}
+
+impl DirSizes {
+    pub fn spawn() -> DirSizes {
+        let (jobs, queue) = mpsc::channel::<PathBuf>();
+        let (done, results) = mpsc::channel();
+
+        thread::spawn(move || {
+            for path in queue {
+                let mut sizes = vec![];
+                if let Ok(meta) = fs::symlink_metadata(&path) {
+                    scan(&path, &meta, &mut HashSet::new(), &mut sizes);
+                }
+                // kfm is quitting
+                if done.send((path, sizes)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        DirSizes {
+            cache: HashMap::new(),
+            pending: HashSet::new(),
+            jobs,
+            results,
+        }
+    }
+
+    // queues `path` unless its size is known or on the way
+    pub fn request(&mut self, path: PathBuf) {
+        if self.cache.contains_key(&path) || self.pending.contains(&path) {
+            return;
+        }
+        self.pending.insert(path.clone());
+        let _ = self.jobs.send(path);
+    }
+
+    // forgets the size of `path` and works it out again
+    pub fn recompute(&mut self, path: PathBuf) {
+        self.cache.remove(&path);
+        self.request(path);
+    }
+
+    // forgets the sizes that change when something in `path` changes: its
+    // own, those of the dirs above it and those of the dirs under it
+    pub fn forget(&mut self, path: &Path) {
+        self.cache
+            .retain(|p, _| !path.starts_with(p) && !p.starts_with(path));
+    }
+
+    // moves finished sizes into the cache, returns whether there were any
+    pub fn poll(&mut self) -> bool {
+        let mut received = false;
+        while let Ok((path, sizes)) = self.results.try_recv() {
+            self.pending.remove(&path);
+            self.cache.extend(sizes);
+            received = true;
+        }
+        received
+    }
+
+    pub fn get(&self, path: &Path) -> Option<u64> {
+        self.cache.get(path).copied()
+    }
+
+    // the space that removing `path` frees, None for a dir that hasn't been
+    // sized. A file with other hard links keeps its space
+    pub fn freed_by(&self, path: &Path) -> Option<u64> {
+        let meta = fs::symlink_metadata(path).ok()?;
+        if meta.is_dir() {
+            self.get(path)
+        } else if meta.nlink() > 1 {
+            Some(0)
+        } else {
+            Some(disk_usage(&meta))
+        }
+    }
+
+    // keeps the sizes up to date without a rescan after `path`, which freed
+    // `size`, was removed or moved under `to`: the dirs above it that aren't
+    // above `to` shrink, the ones only above `to` are forgotten and so are
+    // the ones under it
+    pub fn removed(&mut self, path: &Path, size: u64, to: Option<&Path>) {
+        self.cache.retain(|p, _| !p.starts_with(path));
+        for dir in path.ancestors().skip(1) {
+            if to.is_some_and(|to| to.starts_with(dir)) {
+                break;
+            }
+            if let Some(total) = self.cache.get_mut(dir) {
+                *total = total.saturating_sub(size);
+            }
+        }
+        for dir in to.into_iter().flat_map(Path::ancestors) {
+            if path.starts_with(dir) {
+                break;
+            }
+            self.cache.remove(dir);
+        }
+    }
+}
+
+// the space `meta` takes up on disk, which is what du counts
+pub fn disk_usage(meta: &fs::Metadata) -> u64 {
+    meta.blocks() * 512
+}
+
+// disk usage of `dir` and everything under it, without following symlinks,
+// crossing into other filesystems or counting a hard linked file twice,
+// adding the size of every dir on the way to `sizes`
+fn scan(
+    dir: &Path,
+    meta: &fs::Metadata,
+    seen: &mut HashSet<(u64, u64)>,
+    sizes: &mut Vec<(PathBuf, u64)>,
+) -> u64 {
+    let mut total = disk_usage(meta);
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let entry_meta = match entry.metadata() {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+            if entry_meta.dev() != meta.dev() {
+                continue;
+            }
+            if entry_meta.is_dir() {
+                total += scan(&entry.path(), &entry_meta, seen, sizes);
+            } else if entry_meta.nlink() == 1 || seen.insert((entry_meta.dev(), entry_meta.ino())) {
+                total += disk_usage(&entry_meta);
+            }
+        }
+    }
+    sizes.push((dir.to_path_buf(), total));
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, process::Command, thread, time::Duration};
+
+    use super::*;
+    use crate::utils::TestDir;
+
+    fn sizes_of(dir: &Path) -> HashMap<PathBuf, u64> {
+        let mut sizes = vec![];
+        scan(
+            dir,
+            &fs::metadata(dir).unwrap(),
+            &mut HashSet::new(),
+            &mut sizes,
+        );
+        sizes.into_iter().collect()
+    }
+
+    // what du says, in bytes
+    fn du(dir: &Path) -> u64 {
+        let output = Command::new("du").arg("-sB1").arg(dir).output().unwrap();
+        let output = String::from_utf8_lossy(&output.stdout);
+        output.split_whitespace().next().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn sizes_are_disk_usage_like_du() {
+        let dir = TestDir::new();
+        dir.write("sub/data", vec![1; 100_000]);
+        // a sparse file takes up next to nothing
+        File::create(dir.join("sub/sparse"))
+            .unwrap()
+            .set_len(1 << 30)
+            .unwrap();
+        // and hard links are counted once
+        fs::hard_link(dir.join("sub/data"), dir.join("sub/link")).unwrap();
+
+        let sizes = sizes_of(&dir);
+        assert!(sizes[&dir.join("sub")] < 1 << 20);
+        assert_eq!(sizes[&dir.to_path_buf()], du(&dir));
+        assert_eq!(sizes[&dir.join("sub")], du(&dir.join("sub")));
+    }
+
+    // a DirSizes that has scanned `dir`
+    fn sized(dir: &Path) -> DirSizes {
+        let mut sizes = DirSizes::spawn();
+        sizes.request(dir.to_path_buf());
+        for _ in 0..200 {
+            if sizes.poll() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        sizes
+    }
+
+    #[test]
+    fn scans_cache_the_dirs_under_them() {
+        let dir = TestDir::new();
+        dir.write("a/b/c", "c");
+        let sizes = sized(&dir);
+        assert_eq!(sizes.get(&dir), Some(du(&dir)));
+        assert_eq!(sizes.get(&dir.join("a/b")), Some(du(&dir.join("a/b"))));
+    }
+
+    #[test]
+    fn removing_takes_the_size_off_the_dirs_above() {
+        let dir = TestDir::new();
+        dir.write("a/b/c", vec![1; 100_000]);
+        dir.write("a/d", "d");
+        dir.write("trash/e", "e");
+        let mut sizes = sized(&dir);
+
+        let b = dir.join("a/b");
+        let freed = sizes.freed_by(&b).unwrap();
+        fs::remove_dir_all(&b).unwrap();
+        sizes.removed(&b, freed, None);
+        assert_eq!(sizes.get(&dir), Some(du(&dir)));
+        assert_eq!(sizes.get(&dir.join("a")), Some(du(&dir.join("a"))));
+        assert_eq!(sizes.get(&b), None);
+
+        // moved into the trash, the dirs above both keep their size
+        let total = sizes.get(&dir);
+        let d = dir.join("a/d");
+        let freed = sizes.freed_by(&d).unwrap();
+        fs::rename(&d, dir.join("trash/d")).unwrap();
+        sizes.removed(&d, freed, Some(&dir.join("trash")));
+        assert_eq!(sizes.get(&dir.join("a")), Some(du(&dir.join("a"))));
+        assert_eq!(sizes.get(&dir.join("trash")), None);
+        assert_eq!(sizes.get(&dir), total);
+    }
+}
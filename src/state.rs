@@ -1,10 +1,35 @@
-use tui::widgets::ListState;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
 
-use crate::commands::ls;
+use tui::{layout::Rect, widgets::ListState};
 
+use crate::{
+    commands::ls,
+    error::Error,
+    finder::Finder,
+    git::{self, GitStatus},
+    grep::Grep,
+    links::{self, Link},
+    perms::PermsEditor,
+    plugins::{self, Response},
+    settings::{Settings, Sort},
+    sizes::{self, DirSizes},
+    utils,
+};
+
+#[derive(Clone, Default)]
 pub struct StatefulList<T> {
     pub state: ListState,
     items: Vec<T>,
+    // first visible item, tracked the same way tui scrolls the list since
+    // ListState keeps its own offset private
+    offset: usize,
 }
 
 impl<T> StatefulList<T> {
@@ -12,10 +37,17 @@ impl<T> StatefulList<T> {
         let mut state = ListState::default();
         state.select(Some(0)); // initialize state with '../' selected
 
-        StatefulList { state, items }
+        StatefulList {
+            state,
+            items,
+            offset: 0,
+        }
     }
 
     pub fn next(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i >= self.items.len() - 1 {
@@ -30,6 +62,9 @@ impl<T> StatefulList<T> {
     }
 
     pub fn previous(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
@@ -43,7 +78,38 @@ impl<T> StatefulList<T> {
         self.state.select(Some(i));
     }
 
-    pub fn get_selected(&mut self) -> Option<&T> {
+    // moves the selection by `delta` without wrapping around
+    pub fn scroll(&mut self, delta: isize) {
+        if self.items.is_empty() {
+            return;
+        }
+        let i = self.state.selected().unwrap_or(0) as isize + delta;
+        self.state
+            .select(Some(i.clamp(0, self.items.len() as isize - 1) as usize));
+    }
+
+    // scrolls so the selection fits in `height` rows, call before each render
+    pub fn sync_offset(&mut self, height: usize) {
+        if self.items.is_empty() || height == 0 {
+            self.offset = 0;
+            return;
+        }
+        let selected = self.state.selected().unwrap_or(0).min(self.items.len() - 1);
+        let offset = self.offset.min(self.items.len() - 1);
+        self.offset = if selected >= offset + height {
+            selected + 1 - height
+        } else {
+            offset.min(selected)
+        };
+    }
+
+    // index of the item drawn on the given row of the list
+    pub fn index_at(&self, row: usize) -> Option<usize> {
+        let i = self.offset + row;
+        (i < self.items.len()).then_some(i)
+    }
+
+    pub fn get_selected(&self) -> Option<&T> {
         if let Some(selected_index) = self.state.selected() {
             self.items.get(selected_index)
         } else {
@@ -56,9 +122,79 @@ pub enum InputMode {
     Normal,
     Editing,
     Deleting,
+    // waiting for y/n before running a user command
+    Confirming,
+    // waiting for y/n before discarding git changes
+    Discarding,
+    // typing a query into the fuzzy finder
+    Finding,
+    // searching file contents
+    Grepping,
+    // waiting for y/n before applying a bulk rename
+    Renaming,
+    // editing permissions and ownership in a popup
+    Permissions,
+    // picking the kind of link to make to the marked entries
+    Linking,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChooseMode {
+    Files,
+    Dir,
+}
+
+// picker mode, entries are chosen instead of opened and written to `output`
+#[derive(Clone)]
+pub struct Chooser {
+    pub mode: ChooseMode,
+    // file the chosen paths are written to, - for stdout
+    pub output: String,
+    // separate paths with NUL instead of newlines
+    pub nul: bool,
+}
+
+// a tab's cwd, listing and view settings, kept here while another tab is
+// active, the unfocused pane of the dual-pane layout is kept the same way
+#[derive(Default)]
+pub struct Tab {
+    pub cwd: PathBuf,
+    items: StatefulList<String>,
+    columns: HashMap<String, String>,
+    git: Option<GitStatus>,
+    links: HashMap<String, Link>,
+    hide: bool,
+    long: bool,
+    // the tab's unfocused pane while another tab is active
+    pane: Option<Box<Tab>>,
+    right_focused: bool,
+}
+
+impl Tab {
+    pub fn files(&self) -> &[String] {
+        &self.items.items
+    }
+
+    pub fn columns(&self) -> &HashMap<String, String> {
+        &self.columns
+    }
+
+    pub fn git(&self) -> Option<&GitStatus> {
+        self.git.as_ref()
+    }
+
+    pub fn links(&self) -> &HashMap<String, Link> {
+        &self.links
+    }
+
+    pub fn list_state(&self) -> ListState {
+        self.items.state.clone()
+    }
 }
 
 pub struct App {
+    // dir shown in the nav window, entries and commands are resolved against it
+    pub cwd: PathBuf,
     // files/dirs in nav window
     pub items: StatefulList<String>,
     // current value of input field
@@ -68,24 +204,322 @@ pub struct App {
     pub input_field_title: String,
     pub prev: bool,
     pub file_cont: String,
+    // absolute paths of marked files/dirs
+    pub marked: BTreeSet<String>,
+    pub settings: Settings,
+    // show the help popup
+    pub help: bool,
+    // user command waiting for confirmation
+    pub pending_command: Option<String>,
+    // bulk renames waiting for confirmation, old path -> new path
+    pub pending_renames: Vec<(PathBuf, PathBuf)>,
+    // entry name -> text from column provider plugins
+    pub columns: HashMap<String, String>,
+    pub chooser: Option<Chooser>,
+    // None outside of a git work tree
+    pub git: Option<GitStatus>,
+    // dir the columns and git status are for, and the new ones while they
+    // are worked out in the background
+    decorated_dir: PathBuf,
+    column_results: Option<Receiver<HashMap<String, String>>>,
+    git_results: Option<Receiver<Option<GitStatus>>>,
+    // a plugin's preview of the selected file, and the response of an
+    // action plugin with the hide setting it was started with
+    pub preview_results: Option<Receiver<String>>,
+    pub action_results: Option<Receiver<(Result<Response, String>, bool)>>,
+    // entry name -> symlink target, for the symlinks in the listing
+    pub links: HashMap<String, Link>,
+    // every open tab, the active one's listing lives in the fields above
+    pub tabs: Vec<Tab>,
+    // index of the active tab
+    pub tab: usize,
+    // the active tab's unfocused pane, Some in the dual-pane layout
+    pub other_pane: Option<Tab>,
+    // the focused pane is the right one
+    pub right_focused: bool,
+    // where the last frame drew the focused list, the unfocused pane and the
+    // preview, for mouse clicks
+    pub nav_area: Rect,
+    pub other_pane_area: Rect,
+    pub preview_area: Rect,
+    // lines scrolled past in the preview
+    pub preview_scroll: u16,
+    // message for the status line and when it was reported
+    pub status: Option<(String, Instant)>,
+    // every error reported so far, oldest first
+    pub errors: Vec<String>,
+    // show the error log popup
+    pub error_log: bool,
+    pub sizes: DirSizes,
+    // disk usage mode, entries sorted by size with bars
+    pub usage: bool,
+    pub finder: Option<Finder>,
+    pub grep: Option<Grep>,
+    pub perms: Option<PermsEditor>,
+    // rows of the properties popup while it is open
+    pub properties: Option<Vec<(&'static str, String)>>,
 }
 
+// how long a message stays in the status line
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl App {
-    pub fn new() -> App {
+    pub fn new(settings: Settings, cwd: PathBuf) -> App {
         let mut a = App {
+            cwd,
             items: StatefulList::with_items(vec![]),
             input: String::new(),
             input_mode: InputMode::Normal,
             input_field_title: String::new(),
             prev: false,
             file_cont: String::from(""),
+            marked: BTreeSet::new(),
+            settings,
+            help: false,
+            pending_command: None,
+            pending_renames: vec![],
+            columns: HashMap::new(),
+            decorated_dir: PathBuf::new(),
+            column_results: None,
+            git_results: None,
+            preview_results: None,
+            action_results: None,
+            chooser: None,
+            git: None,
+            links: HashMap::new(),
+            tabs: vec![],
+            tab: 0,
+            other_pane: None,
+            right_focused: false,
+            nav_area: Rect::default(),
+            other_pane_area: Rect::default(),
+            preview_area: Rect::default(),
+            preview_scroll: 0,
+            status: None,
+            errors: vec![],
+            error_log: false,
+            sizes: DirSizes::spawn(),
+            usage: false,
+            finder: None,
+            grep: None,
+            perms: None,
+            properties: None,
         };
-        a.new_cwd("./", true);
-        return a;
+        let hide = !a.settings.hidden;
+        a.new_cwd("./", hide);
+        a.tabs.push(a.snapshot(hide, false));
+        a
+    }
+
+    // the active tab's listing
+    fn snapshot(&self, hide: bool, long: bool) -> Tab {
+        Tab {
+            cwd: self.cwd.clone(),
+            items: self.items.clone(),
+            columns: self.columns.clone(),
+            git: self.git.clone(),
+            links: self.links.clone(),
+            hide,
+            long,
+            pane: None,
+            right_focused: false,
+        }
+    }
+
+    // the active tab's state, taking its second pane along
+    fn take_tab(&mut self, hide: bool, long: bool) -> Tab {
+        let mut tab = self.snapshot(hide, long);
+        tab.pane = self.other_pane.take().map(Box::new);
+        tab.right_focused = self.right_focused;
+        tab
+    }
+
+    // opens a copy of the active tab's listing next to it and switches to it
+    pub fn new_tab(&mut self, hide: bool, long: bool) {
+        let tab = self.snapshot(hide, long);
+        self.tabs[self.tab] = self.take_tab(hide, long);
+        self.tabs.insert(self.tab + 1, tab);
+        self.tab += 1;
+        self.right_focused = false;
+    }
+
+    // switches to the tab at `index`, returning its hide and long settings
+    pub fn switch_tab(&mut self, index: usize, hide: bool, long: bool) -> (bool, bool) {
+        if index >= self.tabs.len() || index == self.tab {
+            return (hide, long);
+        }
+        self.tabs[self.tab] = self.take_tab(hide, long);
+        self.load_tab(index)
+    }
+
+    // closes the active tab unless it is the last one, returning the hide and
+    // long settings of the tab that becomes active
+    pub fn close_tab(&mut self, hide: bool, long: bool) -> (bool, bool) {
+        if self.tabs.len() == 1 {
+            return (hide, long);
+        }
+        self.tabs.remove(self.tab);
+        self.load_tab(self.tab.min(self.tabs.len() - 1))
+    }
+
+    fn load_tab(&mut self, index: usize) -> (bool, bool) {
+        self.tab = index;
+        let mut tab = std::mem::take(&mut self.tabs[index]);
+        self.other_pane = tab.pane.take().map(|pane| *pane);
+        self.right_focused = tab.right_focused;
+        self.load(tab)
+    }
+
+    // makes `tab` the live listing, returning its hide and long settings
+    fn load(&mut self, tab: Tab) -> (bool, bool) {
+        let (hide, long) = (tab.hide, tab.long);
+        self.restore(tab);
+
+        // the listing may be out of date
+        let args = if long { "-l" } else { "./" };
+        self.relist(args, hide);
+        (hide, long)
+    }
+
+    // puts back the listing of `tab` as it was
+    fn restore(&mut self, tab: Tab) {
+        self.items = tab.items;
+        self.columns = tab.columns;
+        self.decorated_dir = tab.cwd.clone();
+        self.git = tab.git;
+        self.links = tab.links;
+        self.cwd = tab.cwd;
+    }
+
+    // lists the second pane's dir again after something in it changed, it
+    // keeps its columns and git status until it gets focus
+    pub fn refresh_other_pane(&mut self) {
+        let pane = match self.other_pane.take() {
+            Some(pane) => pane,
+            None => return,
+        };
+        let results = (self.column_results.take(), self.git_results.take());
+        // hide and long aren't needed to put the listing back
+        let current = self.snapshot(false, false);
+        let (hide, long) = self.load(pane);
+        self.other_pane = Some(self.snapshot(hide, long));
+        self.restore(current);
+        (self.column_results, self.git_results) = results;
+    }
+
+    // switches between the single list and the dual-pane layout, a new
+    // second pane starts in the current dir
+    pub fn toggle_panes(&mut self, hide: bool, long: bool) {
+        self.other_pane = match self.other_pane {
+            Some(_) => None,
+            None => Some(self.snapshot(hide, long)),
+        };
+        self.right_focused = false;
+    }
+
+    // moves focus to the other pane, returning its hide and long settings
+    pub fn switch_pane(&mut self, hide: bool, long: bool) -> (bool, bool) {
+        let current = self.snapshot(hide, long);
+        match self.other_pane.replace(current) {
+            Some(other) => {
+                self.right_focused = !self.right_focused;
+                self.load(other)
+            }
+            None => {
+                self.other_pane = None;
+                (hide, long)
+            }
+        }
+    }
+
+    // dir name of each tab, for the tab bar
+    pub fn tab_titles(&self) -> Vec<String> {
+        self.tabs
+            .iter()
+            .enumerate()
+            .map(|(i, tab)| {
+                let cwd = if i == self.tab { &self.cwd } else { &tab.cwd };
+                match cwd.file_name() {
+                    Some(name) => name.to_string_lossy().into_owned(),
+                    None => cwd.to_string_lossy().into_owned(),
+                }
+            })
+            .collect()
+    }
+
+    // shows an error in the status line and adds it to the log
+    pub fn report(&mut self, error: Error) {
+        let message = error.to_string();
+        self.errors.push(message.clone());
+        self.status = Some((message, Instant::now()));
+    }
+
+    // the status line message, until it times out
+    pub fn status_message(&self) -> Option<&str> {
+        match &self.status {
+            Some((message, at)) if at.elapsed() < STATUS_TIMEOUT => Some(message),
+            _ => None,
+        }
+    }
+
+    // takes in results from background work, returns whether the screen
+    // needs redrawing
+    pub fn poll_background(&mut self) -> bool {
+        let sized = self.sizes.poll();
+        if sized {
+            self.sort_by_size();
+        }
+        let columns = take_result(&mut self.column_results)
+            .map(|columns| self.columns = columns)
+            .is_some();
+        let git = take_result(&mut self.git_results)
+            .map(|git| self.git = git)
+            .is_some();
+        // only shown while the preview is still open
+        let previewed = take_result(&mut self.preview_results)
+            .map(|preview| {
+                if self.prev {
+                    self.file_cont = preview
+                }
+            })
+            .is_some();
+        let acted = take_result(&mut self.action_results)
+            .map(|(response, hide)| match response {
+                Ok(response) => plugins::apply(response.actions, self, hide),
+                Err(e) => self.report(Error::Plugin(e)),
+            })
+            .is_some();
+        let found = self.finder.as_mut().is_some_and(|f| f.poll());
+        let matched = self.grep.as_mut().is_some_and(|g| g.poll());
+        sized || columns || git || previewed || acted || found || matched
+    }
+
+    // time left before the status line should be cleared
+    pub fn status_timeout(&self) -> Option<Duration> {
+        self.status
+            .as_ref()
+            .map(|(_, at)| STATUS_TIMEOUT.saturating_sub(at.elapsed()))
+    }
+
+    // the current dir as a string, for building paths of entries
+    pub fn working_dir(&self) -> String {
+        self.cwd.to_string_lossy().into_owned()
     }
 
     pub fn new_cwd(&mut self, args: &str, hide_dot_files: bool) {
-        let files = ls(args);
+        // the dir may have been removed from under us
+        if !self.cwd.is_dir() {
+            self.cwd = utils::existing_ancestor(&self.cwd);
+        }
+
+        let listing = match self.settings.sort.ls_flag() {
+            Some(flag) => ls(&self.cwd, &(args.to_string() + " " + flag)),
+            None => ls(&self.cwd, args),
+        };
+        let files = listing.unwrap_or_else(|e| {
+            self.report(Error::Io(self.working_dir(), e));
+            vec![]
+        });
         let raw_items: Vec<&str> = files.iter().map(|s| s.as_str()).collect();
         let mut items: Vec<String>;
         if hide_dot_files {
@@ -99,33 +533,293 @@ impl App {
         }
 
         // remove total count when showing permissions
-        if args.contains('l') {
+        if args.contains('l') && !items.is_empty() {
             items = items[1..].to_vec();
         }
 
-        // sort dirs before files, then by name
-        items.sort_by(|a, b| {
-            let a_last_char_slash = a.chars().last() == Some('/');
-            let b_last_char_slash = b.chars().last() == Some('/');
+        // sort dirs before files, keeping the order from ls within each
+        items.sort_by_key(|e| !e.ends_with('/'));
 
-            if a_last_char_slash && !b_last_char_slash {
-                std::cmp::Ordering::Less
-            } else {
-                std::cmp::Ordering::Greater
+        // ../ before ./, both before everything else
+        for (i, special) in ["../", "./"].iter().enumerate() {
+            let long_name = " ".to_string() + special;
+            if let Some(position) = items
+                .iter()
+                .position(|e| e == special || e.ends_with(&long_name))
+            {
+                let entry = items.remove(position);
+                items.insert(i.min(items.len()), entry);
             }
+        }
+
+        self.items = StatefulList::with_items(items);
+        if self.usage {
+            self.sizes.request(self.cwd.clone());
+        } else if self.settings.dir_sizes {
+            for dir in self.dirs() {
+                self.sizes.request(dir);
+            }
+        }
+        self.sort_by_size();
+        // the columns and git status of the last listing are kept until the
+        // new ones are in, unless they are for another dir
+        if self.decorated_dir != self.cwd {
+            self.columns.clear();
+            self.git = None;
+            self.decorated_dir = self.cwd.clone();
+        }
+        self.column_results = plugins::columns(self);
+        let (tx, rx) = mpsc::channel();
+        let (cwd, entries) = (self.working_dir(), self.items.items.clone());
+        thread::spawn(move || {
+            // the listing changed before git was done
+            let _ = tx.send(git::status(&cwd, &entries));
         });
+        self.git_results = Some(rx);
+        self.links = links::read(&self.cwd, &self.items.items);
+    }
 
-        // ../ before ./
-        items.swap(0, 1);
+    // paths of the dirs in the listing
+    fn dirs(&self) -> Vec<PathBuf> {
+        self.items
+            .items
+            .iter()
+            .map(|e| utils::entry_name(e))
+            .filter(|e| e.ends_with('/') && *e != "./" && *e != "../")
+            .map(|e| self.cwd.join(e.trim_end_matches('/')))
+            .collect()
+    }
 
-        self.items = StatefulList::with_items(items);
+    // works out the size of every dir in the listing again
+    pub fn size_dirs(&mut self) {
+        for dir in self.dirs() {
+            self.sizes.recompute(dir);
+        }
+    }
+
+    // size of an entry in the listing, recursive for dirs once it is known
+    pub fn entry_size(&self, entry: &str) -> Option<u64> {
+        let name = utils::entry_name(entry);
+        if name == "./" || name == "../" {
+            return None;
+        }
+        let path = self.cwd.join(name.trim_end_matches('/'));
+        if name.ends_with('/') {
+            self.sizes.get(&path)
+        } else if self.usage {
+            // comparable with the dirs, which are sized like du does
+            path.symlink_metadata().ok().map(|m| sizes::disk_usage(&m))
+        } else {
+            path.symlink_metadata().ok().map(|m| m.len())
+        }
+    }
+
+    // orders entries by size, biggest first with the ones not worked out yet
+    // last: every entry in usage mode, dirs among themselves when sorting by
+    // size
+    pub fn sort_by_size(&mut self) {
+        if !self.usage && self.settings.sort != Sort::Size {
+            return;
+        }
+        let selected = self.selected_name().map(String::from);
+        let mut items = std::mem::take(&mut self.items.items);
+        items.sort_by_cached_key(|e| {
+            let special = matches!(utils::entry_name(e), "./" | "../");
+            let size = if self.usage || e.ends_with('/') {
+                self.entry_size(e)
+            } else {
+                None
+            };
+            let group = !self.usage && !e.ends_with('/');
+            (!special, group, Reverse(size))
+        });
+        self.items.items = items;
+        if let Some(name) = selected {
+            self.select(&name);
+        }
+    }
+
+    // switches usage mode, which lists the current dir like ncdu
+    pub fn toggle_usage(&mut self, hide: bool) {
+        self.usage = !self.usage;
+        self.relist("./", hide);
+    }
+
+    // reloads the listing, keeping the selected entry selected
+    pub fn refresh(&mut self, hide_dot_files: bool) {
+        self.relist("./", hide_dot_files);
+    }
+
+    fn relist(&mut self, args: &str, hide_dot_files: bool) {
+        let selected = self.selected_name().map(String::from);
+        self.new_cwd(args, hide_dot_files);
+        if let Some(name) = selected {
+            self.select(&name);
+        }
+    }
+
+    // selects the entry with the given name, returns false if there is none
+    pub fn select(&mut self, name: &str) -> bool {
+        let position = self
+            .items
+            .items
+            .iter()
+            .map(|e| utils::entry_name(e))
+            .position(|e| e == name || e.trim_end_matches('/') == name);
+        if position.is_some() {
+            self.items.state.select(position);
+        }
+        position.is_some()
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.items.items.len()
     }
 
     pub fn current_files(&self) -> Vec<String> {
-        return self.items.items.clone();
+        self.items.items.clone()
     }
 
-    pub fn clear_input(&mut self) -> () {
+    // file names of the entries, which differ from the entries in long mode
+    pub fn entry_names(&self) -> Vec<String> {
+        self.items
+            .items
+            .iter()
+            .map(|e| utils::entry_name(e).to_string())
+            .collect()
+    }
+
+    // file name of the selected entry
+    pub fn selected_name(&self) -> Option<&str> {
+        self.items.get_selected().map(|e| utils::entry_name(e))
+    }
+
+    pub fn toggle_mark(&mut self, path: String) {
+        if !self.marked.remove(&path) {
+            self.marked.insert(path);
+        }
+    }
+
+    // name of the plugin action bound to the given key
+    pub fn plugin_for_key(&self, key: char) -> Option<String> {
+        self.settings
+            .plugins
+            .iter()
+            .find(|(_, p)| p.key == Some(key))
+            .map(|(name, _)| name.clone())
+    }
+
+    // name of the user command bound to the given key
+    pub fn command_for_key(&self, key: char) -> Option<String> {
+        self.settings
+            .commands
+            .iter()
+            .find(|(_, c)| c.key == Some(key))
+            .map(|(name, _)| name.clone())
+    }
+
+    pub fn clear_input(&mut self) {
         self.input = String::new();
     }
 }
+
+// the value sent on `results` once it is in, the channel is dropped after it
+// or when the sender goes away without sending
+fn take_result<T>(results: &mut Option<Receiver<T>>) -> Option<T> {
+    let result = results.as_ref()?.try_recv();
+    if !matches!(result, Err(TryRecvError::Empty)) {
+        *results = None;
+    }
+    result.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TestDir;
+
+    // the dir holding `a/` and `b/`, listed
+    fn app(dir: &TestDir) -> App {
+        dir.write("a/x", "");
+        dir.write("b/y", "");
+        App::new(Settings::default(), dir.to_path_buf())
+    }
+
+    fn cd(app: &mut App, dir: &str, hide: bool) {
+        app.cwd = app.cwd.join(dir);
+        app.new_cwd("./", hide);
+    }
+
+    #[test]
+    fn tabs_keep_their_own_dir_selection_and_view() {
+        let dir = TestDir::new();
+        let mut app = app(&dir);
+        assert!(app.select("b"));
+
+        // a new tab starts as a copy of the active one
+        app.new_tab(true, false);
+        assert_eq!((app.tabs.len(), app.tab), (2, 1));
+        assert_eq!(app.cwd, dir.to_path_buf());
+        assert_eq!(app.selected_name(), Some("b/"));
+        cd(&mut app, "a", false);
+        assert!(app.select("x"));
+
+        assert_eq!(app.switch_tab(0, false, true), (true, false));
+        assert_eq!(app.cwd, dir.to_path_buf());
+        assert_eq!(app.selected_name(), Some("b/"));
+        assert_eq!(
+            app.tab_titles(),
+            [dir.file_name().unwrap().to_str().unwrap(), "a"]
+        );
+
+        assert_eq!(app.switch_tab(1, true, false), (false, true));
+        assert_eq!(app.cwd, dir.join("a"));
+        // the long view came back with the tab
+        assert!(app.selected_name() == Some("x") && app.items.items[2].starts_with('-'));
+
+        // switching to a missing or the active tab does nothing
+        assert_eq!(app.switch_tab(5, false, true), (false, true));
+        assert_eq!(app.switch_tab(1, false, true), (false, true));
+        assert_eq!(app.tab, 1);
+    }
+
+    #[test]
+    fn closing_tabs() {
+        let dir = TestDir::new();
+        let mut app = app(&dir);
+        app.new_tab(true, false);
+        cd(&mut app, "a", true);
+        app.new_tab(true, false);
+        cd(&mut app, "../b", true);
+        app.switch_tab(1, true, false);
+
+        // the tab after the closed one takes its place
+        app.close_tab(true, false);
+        assert_eq!((app.tabs.len(), app.tab), (2, 1));
+        assert_eq!(app.cwd, dir.join("a/../b"));
+        // the last tab becomes active when the last one is closed
+        app.close_tab(true, false);
+        assert_eq!((app.tabs.len(), app.tab), (1, 0));
+        assert_eq!(app.cwd, dir.to_path_buf());
+        // the last tab stays open
+        assert_eq!(app.close_tab(false, true), (false, true));
+        assert_eq!(app.tabs.len(), 1);
+    }
+
+    #[test]
+    fn tabs_keep_their_second_pane() {
+        let dir = TestDir::new();
+        let mut app = app(&dir);
+        app.toggle_panes(true, false);
+        cd(&mut app, "a", true);
+        app.new_tab(true, false);
+        assert!(app.other_pane.is_none());
+
+        app.switch_tab(0, true, false);
+        assert_eq!(app.other_pane.as_ref().unwrap().cwd, dir.to_path_buf());
+        app.switch_pane(true, false);
+        assert_eq!(app.cwd, dir.to_path_buf());
+        assert_eq!(app.other_pane.as_ref().unwrap().cwd, dir.join("a"));
+        assert!(app.right_focused);
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    ffi::{CStr, CString},
+    fs::{self, Metadata},
+    mem,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::state::App;
+
+// fields of the status bar format string:
+//
+// {size}    size of the selected entry
+// {perms}   its permissions, like ls -l
+// {mtime}   when it was last modified
+// {pos}     position of the selection, like 12/340
+// {marked}  number of marked entries and their total size, empty when none
+// {sort}    order of the listing
+// {filter}  "hidden" when dot files are shown
+// {free}    free space on the current filesystem
+pub const DEFAULT_FORMAT: &str =
+    "{perms} {size} {mtime}  {pos}  {marked}  {sort} {filter}  {free} free";
+
+// the status bar text for the current selection
+pub fn format(template: &str, app: &mut App, hide: bool) -> String {
+    let total = app.entry_count();
+    let position = app.items.state.selected().map(|i| i + 1).unwrap_or(0);
+    let path = app
+        .selected_name()
+        .filter(|name| *name != "./" && *name != "../")
+        .map(|name| app.cwd.join(name.trim_end_matches('/')));
+    let meta = path.as_ref().and_then(|p| fs::symlink_metadata(p).ok());
+    // dirs show their recursive size once it is known
+    let size = match (&path, &meta) {
+        (Some(p), Some(m)) if m.is_dir() => app.sizes.get(p).or(Some(m.len())),
+        (_, m) => m.as_ref().map(|m| m.len()),
+    };
+
+    let marked = if app.marked.is_empty() {
+        String::new()
+    } else {
+        let size = app
+            .marked
+            .iter()
+            .filter_map(|m| fs::symlink_metadata(m).ok())
+            .map(|m| m.len())
+            .sum();
+        format!("{} marked, {}", app.marked.len(), human_size(size))
+    };
+
+    let mut res = template.to_string();
+    for (field, value) in [
+        ("{size}", size.map(human_size)),
+        ("{perms}", meta.as_ref().map(permissions)),
+        (
+            "{mtime}",
+            meta.as_ref()
+                .and_then(|m| m.modified().ok())
+                .map(format_time),
+        ),
+        ("{pos}", Some(format!("{}/{}", position, total))),
+        ("{marked}", Some(marked)),
+        ("{sort}", Some(app.settings.sort.name().to_string())),
+        ("{filter}", (!hide).then(|| String::from("hidden"))),
+        ("{free}", free_space(&app.cwd).map(human_size)),
+    ] {
+        if res.contains(field) {
+            res = res.replace(field, &value.unwrap_or_default());
+        }
+    }
+
+    res
+}
+
+// 1234567 -> 1.2M
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if size < 10.0 {
+        format!("{:.1}{}", size, UNITS[unit])
+    } else {
+        format!("{:.0}{}", size, UNITS[unit])
+    }
+}
+
+// drwxr-xr-x
+pub fn permissions(meta: &Metadata) -> String {
+    let kind = if meta.file_type().is_symlink() {
+        'l'
+    } else if meta.is_dir() {
+        'd'
+    } else {
+        '-'
+    };
+    let mode = meta.permissions().mode();
+    let mut res = String::from(kind);
+    for (i, c) in "rwxrwxrwx".chars().enumerate() {
+        res.push(if mode & (0o400 >> i) != 0 { c } else { '-' });
+    }
+    res
+}
+
+// local time, like 2023-04-28 14:03
+pub fn format_time(time: SystemTime) -> String {
+    strftime(time, c"%Y-%m-%d %H:%M")
+}
+
+// local time in a strftime(3) format
+pub fn strftime(time: SystemTime, format: &CStr) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as libc::time_t)
+        .unwrap_or(0);
+    let mut buf = [0u8; 64];
+    // SAFETY: tm is filled in by localtime_r before strftime reads it, and
+    // strftime writes at most buf.len() bytes
+    let len = unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        if libc::localtime_r(&secs, &mut tm).is_null() {
+            return String::new();
+        }
+        libc::strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+// bytes available to unprivileged users on the filesystem holding `path`
+#[allow(clippy::unnecessary_cast)] // the field types differ between platforms
+pub fn free_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs only writes to the struct it is given
+    let stat = unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return None;
+        }
+        stat
+    };
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{settings::Settings, utils::TestDir};
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(human_size(0), "0B");
+        assert_eq!(human_size(1023), "1023B");
+        assert_eq!(human_size(1024), "1.0K");
+        assert_eq!(human_size(1536), "1.5K");
+        assert_eq!(human_size(20 * 1024), "20K");
+        assert_eq!(human_size(1234567), "1.2M");
+        assert_eq!(human_size(5 << 30), "5.0G");
+        assert_eq!(human_size(u64::MAX), "16E");
+    }
+
+    #[test]
+    fn permission_strings() {
+        let dir = TestDir::new();
+        let file = dir.write("a", "");
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        assert_eq!(permissions(&fs::metadata(&file).unwrap()), "-rw-r-----");
+        fs::set_permissions(&*dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(permissions(&fs::metadata(&*dir).unwrap()), "drwxr-xr-x");
+        std::os::unix::fs::symlink("a", dir.join("link")).unwrap();
+        let link = fs::symlink_metadata(dir.join("link")).unwrap();
+        assert!(permissions(&link).starts_with('l'));
+    }
+
+    #[test]
+    fn fields_are_filled_in() {
+        let dir = TestDir::new();
+        let file = dir.write("a", "hello");
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        dir.write("b", vec![0; 2048]);
+        dir.write("sub/c", vec![0; 100_000]);
+        let mut app = App::new(Settings::default(), dir.to_path_buf());
+        assert!(app.select("a"));
+        let pos = app.items.state.selected().unwrap() + 1;
+
+        let template = "{pos} {perms} {size} [{marked}] {sort} [{filter}] {unknown}";
+        assert_eq!(
+            format(template, &mut app, true),
+            format!("{}/5 -rw-r--r-- 5B [] name [] {{unknown}}", pos)
+        );
+        app.marked.insert(file.to_string_lossy().into_owned());
+        app.marked
+            .insert(dir.join("b").to_string_lossy().into_owned());
+        assert_eq!(
+            format("{marked} {filter}", &mut app, false),
+            "2 marked, 2.0K hidden"
+        );
+
+        // ./ and ../ get no details, dirs show their size once it is known
+        assert!(app.select("./"));
+        assert_eq!(
+            format("[{size}] [{perms}] [{mtime}]", &mut app, true),
+            "[] [] []"
+        );
+        assert!(app.select("sub"));
+        let len = fs::metadata(dir.join("sub")).unwrap().len();
+        assert_eq!(format("{size}", &mut app, true), human_size(len));
+        app.sizes.request(dir.join("sub"));
+        while app.sizes.get(&dir.join("sub")).is_none() {
+            app.sizes.poll();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let size = app.sizes.get(&dir.join("sub")).unwrap();
+        assert_eq!(format("{size}", &mut app, true), human_size(size));
+        assert!(!format("{free}", &mut app, true).is_empty());
+    }
+
+    #[test]
+    fn times_are_formatted_like_iso_dates() {
+        let time = format_time(SystemTime::now());
+        assert_eq!(time.len(), "2023-04-28 14:03".len(), "{}", time);
+        assert_eq!(&time[4..5], "-");
+        assert_eq!(&time[13..14], ":");
+    }
+}
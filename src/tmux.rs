@@ -0,0 +1,188 @@
+use std::{path::Path, process::Command};
+
+use crate::{settings::Settings, utils};
+
+// where to open the selected entry
+pub enum Target {
+    Window,
+    // new pane to the right
+    SplitRight,
+    // new pane below
+    SplitBelow,
+    Popup,
+    // an existing window or pane, anything `tmux send-keys -t` accepts
+    Pane(String),
+}
+
+// arguments for a tmux invocation that opens `path` at `target`: dirs get a
+// shell started in them, files are opened in `editor`
+pub fn args(target: &Target, path: &str, editor: &str) -> Vec<String> {
+    let is_dir = Path::new(path).is_dir();
+    let dir = if is_dir {
+        path.to_string()
+    } else {
+        Path::new(path)
+            .parent()
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("/"))
+    };
+    let open_file = format!("{} {}", editor, utils::shell_quote(path));
+
+    let mut args: Vec<String> = match target {
+        Target::Window => vec!["new-window", "-c", &dir],
+        Target::SplitRight => vec!["split-window", "-h", "-c", &dir],
+        Target::SplitBelow => vec!["split-window", "-v", "-c", &dir],
+        Target::Popup => vec!["display-popup", "-E", "-d", &dir],
+        Target::Pane(pane) => {
+            let keys = if is_dir {
+                format!("cd {}", utils::shell_quote(path))
+            } else {
+                open_file
+            };
+            return ["send-keys", "-t", pane, &keys, "Enter"]
+                .iter()
+                .map(|a| a.to_string())
+                .collect();
+        }
+    }
+    .iter()
+    .map(|a| a.to_string())
+    .collect();
+
+    if !is_dir {
+        args.push(open_file);
+    } else if let Target::Popup = target {
+        // a popup needs a command to run, default to the user's shell
+        args.push(std::env::var("SHELL").unwrap_or_else(|_| String::from("sh")));
+    }
+
+    args
+}
+
+// runs tmux, returning its error output if it fails
+pub fn open(target: &Target, path: &str, settings: &Settings) -> Result<(), String> {
+    let binary = settings.tmux.as_deref().unwrap_or("tmux");
+
+    let output = Command::new(binary)
+        .args(args(target, path, settings.editor()))
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", binary, e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::*;
+    use crate::utils::TestDir;
+
+    // a stand-in for tmux that writes its arguments to a file, one per line
+    struct Stub {
+        dir: TestDir,
+        settings: Settings,
+    }
+
+    impl Stub {
+        fn new(script: &str) -> Stub {
+            let dir = TestDir::new();
+            let argv = dir.join("argv");
+            let script = format!(
+                "#!/bin/sh\nprintf '%s\\n' \"$@\" > {}\n{}\n",
+                utils::shell_quote(&argv.to_string_lossy()),
+                script
+            );
+            let binary = dir.write("tmux", script);
+            fs::set_permissions(&binary, fs::Permissions::from_mode(0o700)).unwrap();
+            let settings = Settings {
+                tmux: Some(binary.to_string_lossy().into_owned()),
+                editor: Some(String::from("vim")),
+                ..Settings::default()
+            };
+            Stub { dir, settings }
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.dir.join(name).to_string_lossy().into_owned()
+        }
+
+        fn open(&self, target: Target, path: &str) -> Result<Vec<String>, String> {
+            open(&target, path, &self.settings)?;
+            let argv = fs::read_to_string(self.dir.join("argv")).unwrap();
+            Ok(argv.lines().map(String::from).collect())
+        }
+    }
+
+    #[test]
+    fn splits_open_files_in_the_editor() {
+        let stub = Stub::new("");
+        let file = stub.path("it's");
+        let dir = stub.path("");
+        let dir = dir.trim_end_matches('/');
+
+        let argv = stub.open(Target::SplitRight, &file).unwrap();
+        let open_file = format!("vim {}", utils::shell_quote(&file));
+        assert_eq!(argv, ["split-window", "-h", "-c", dir, &open_file]);
+
+        let argv = stub.open(Target::SplitBelow, &file).unwrap();
+        assert_eq!(argv, ["split-window", "-v", "-c", dir, &open_file]);
+    }
+
+    #[test]
+    fn splits_start_a_shell_in_dirs() {
+        let stub = Stub::new("");
+        let dir = stub.path("sub");
+        fs::create_dir(&dir).unwrap();
+
+        let argv = stub.open(Target::SplitRight, &dir).unwrap();
+        assert_eq!(argv, ["split-window", "-h", "-c", &dir]);
+    }
+
+    #[test]
+    fn popups_run_the_editor() {
+        let stub = Stub::new("");
+        let file = stub.path("notes.txt");
+        let dir = stub.path("");
+
+        let argv = stub.open(Target::Popup, &file).unwrap();
+        let open_file = format!("vim {}", utils::shell_quote(&file));
+        assert_eq!(
+            argv,
+            [
+                "display-popup",
+                "-E",
+                "-d",
+                dir.trim_end_matches('/'),
+                &open_file
+            ]
+        );
+    }
+
+    #[test]
+    fn panes_get_keys_sent() {
+        let stub = Stub::new("");
+        let file = stub.path("notes.txt");
+        let dir = stub.path("sub");
+        fs::create_dir(&dir).unwrap();
+
+        let argv = stub.open(Target::Pane(String::from("1.2")), &file).unwrap();
+        let open_file = format!("vim {}", utils::shell_quote(&file));
+        assert_eq!(argv, ["send-keys", "-t", "1.2", &open_file, "Enter"]);
+
+        let argv = stub.open(Target::Pane(String::from("1.2")), &dir).unwrap();
+        let cd = format!("cd {}", utils::shell_quote(&dir));
+        assert_eq!(argv, ["send-keys", "-t", "1.2", &cd, "Enter"]);
+    }
+
+    #[test]
+    fn failures_return_the_error_output() {
+        let stub = Stub::new("echo 'no server running' >&2; exit 1");
+        let err = stub.open(Target::Window, &stub.path("a")).unwrap_err();
+        assert_eq!(err, "no server running");
+    }
+}
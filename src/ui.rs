@@ -1,20 +1,54 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::Path,
+};
+
 use tui::{
-    style::{Color, Style},
+    style::{Color, Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    widgets::{Block, Borders, List, ListItem, Paragraph, Tabs, Wrap},
 };
 
-use crate::utils;
-
-pub fn navigation_window(files: &Vec<String>) -> List {
-    let title = Span::styled(
-        utils::get_working_dir(),
-        Style::default().fg(Color::LightCyan),
-    );
+use crate::{
+    git::{GitStatus, Status},
+    grep::Match,
+    links::Link,
+    perms::{Field, PermsEditor},
+    settings::{Plugin, UserCommand},
+    sizes::DirSizes,
+    status, utils,
+};
 
+pub fn navigation_window<'a>(
+    cwd: &str,
+    files: &'a [String],
+    marked: &BTreeSet<String>,
+    columns: &HashMap<String, String>,
+    git: Option<&GitStatus>,
+    sizes: &DirSizes,
+    links: &HashMap<String, Link>,
+) -> List<'a> {
     let nav_window_items: Vec<ListItem> = files
         .iter()
         .map(|file| {
+            let name = utils::entry_name(file);
+            // marked entries are prefixed with a yellow '+'
+            let mark_span = if marked.contains(&(cwd.to_string() + "/" + name)) {
+                Span::styled("+", Style::default().fg(Color::LightYellow))
+            } else {
+                Span::raw(" ")
+            };
+
+            let git_span = git_status(git.and_then(|g| g.entries.get(name)));
+
+            // text from column provider plugins follows the name
+            let column_span = match columns.get(name) {
+                Some(text) => {
+                    Span::styled(format!("  {}", text), Style::default().fg(Color::DarkGray))
+                }
+                None => Span::raw(""),
+            };
+
             if let Some(last_char) = file.chars().last() {
                 if last_char == '/' {
                     let prefix: String = file.chars().take(file.len() - 1).collect();
@@ -23,57 +57,468 @@ pub fn navigation_window(files: &Vec<String>) -> List {
                         Span::styled(prefix, Style::default().fg(Color::LightBlue));
                     let suffix_as_span = Span::styled("/", Style::default().fg(Color::LightRed));
 
-                    let formatted_dir = Spans::from(vec![prefix_as_span, suffix_as_span]);
+                    // recursive size, once it has been worked out
+                    let size_span =
+                        match sizes.get(&Path::new(cwd).join(name.trim_end_matches('/'))) {
+                            Some(size) => Span::styled(
+                                format!("  {}", status::human_size(size)),
+                                Style::default().fg(Color::Green),
+                            ),
+                            None => Span::raw(""),
+                        };
+
+                    let formatted_dir = Spans::from(vec![
+                        mark_span,
+                        git_span,
+                        prefix_as_span,
+                        suffix_as_span,
+                        size_span,
+                        column_span,
+                    ]);
 
                     return ListItem::new(formatted_dir);
+                } else if let Some(link) = links.get(file) {
+                    // only short entries, long ones already show the target
+                    // name -> target, red when the target is missing
+                    let (name_style, target_style) = if link.broken {
+                        let broken = Style::default().fg(Color::LightRed);
+                        (broken.add_modifier(Modifier::CROSSED_OUT), broken)
+                    } else if link.dir {
+                        (
+                            Style::default().fg(Color::LightCyan),
+                            Style::default().fg(Color::LightBlue),
+                        )
+                    } else {
+                        (
+                            Style::default().fg(Color::LightCyan),
+                            Style::default().fg(Color::DarkGray),
+                        )
+                    };
+                    return ListItem::new(Spans::from(vec![
+                        mark_span,
+                        git_span,
+                        Span::styled(file.as_str(), name_style),
+                        Span::styled(" -> ", Style::default().fg(Color::DarkGray)),
+                        Span::styled(link.target.to_string_lossy().into_owned(), target_style),
+                        column_span,
+                    ]));
                 } else {
-                    return ListItem::new(file.as_str());
+                    return ListItem::new(Spans::from(vec![
+                        mark_span,
+                        git_span,
+                        Span::raw(file.as_str()),
+                        column_span,
+                    ]));
                 }
             }
-            panic!("file/directory must have a non-empty name");
+            ListItem::new(Spans::from(vec![mark_span, git_span]))
         })
         .collect();
 
-    return List::new(nav_window_items)
-        .highlight_symbol(">> ")
-        .block(Block::default().title(title).borders(Borders::ALL));
+    listing(nav_window_items, cwd, git)
+}
+
+// the listing in usage mode: entries with a bar and their share of the dir's
+// size, biggest first
+pub fn usage_window<'a>(
+    cwd: &str,
+    files: &'a [String],
+    sizes: &[Option<u64>],
+    marked: &BTreeSet<String>,
+    git: Option<&GitStatus>,
+) -> List<'a> {
+    const BAR_WIDTH: usize = 20;
+    let total: u64 = sizes.iter().flatten().sum();
+
+    let items: Vec<ListItem> = files
+        .iter()
+        .zip(sizes)
+        .map(|(file, size)| {
+            let mark_span = if marked.contains(&(cwd.to_string() + "/" + file)) {
+                Span::styled("+", Style::default().fg(Color::LightYellow))
+            } else {
+                Span::raw(" ")
+            };
+
+            let (bar, share, size) = match size {
+                Some(size) => {
+                    let share = if total == 0 {
+                        0.0
+                    } else {
+                        *size as f64 / total as f64
+                    };
+                    let filled = (share * BAR_WIDTH as f64).round() as usize;
+                    (
+                        format!("[{:<width$}]", "#".repeat(filled), width = BAR_WIDTH),
+                        format!("{:>6.1}%", share * 100.0),
+                        format!("{:>6}", status::human_size(*size)),
+                    )
+                }
+                None if file == "./" || file == "../" => {
+                    (" ".repeat(BAR_WIDTH + 2), " ".repeat(7), " ".repeat(6))
+                }
+                None => (
+                    format!("[{:<width$}]", "...", width = BAR_WIDTH),
+                    " ".repeat(7),
+                    " ".repeat(6),
+                ),
+            };
+
+            let name_style = if file.ends_with('/') {
+                Style::default().fg(Color::LightBlue)
+            } else {
+                Style::default()
+            };
+            ListItem::new(Spans::from(vec![
+                mark_span,
+                Span::styled(size, Style::default().fg(Color::Green)),
+                Span::raw(" "),
+                Span::styled(share, Style::default().fg(Color::DarkGray)),
+                Span::raw(" "),
+                Span::styled(bar, Style::default().fg(Color::Cyan)),
+                Span::raw(" "),
+                Span::styled(file.as_str(), name_style),
+            ]))
+        })
+        .collect();
+
+    listing(items, cwd, git)
+}
+
+// the list of entries in a block titled with the dir and its git branch
+fn listing<'a>(items: Vec<ListItem<'a>>, cwd: &str, git: Option<&GitStatus>) -> List<'a> {
+    let mut title = vec![Span::styled(
+        cwd.to_string(),
+        Style::default().fg(Color::LightCyan),
+    )];
+    if let Some(branch) = git.and_then(|g| g.branch.as_ref()) {
+        title.push(Span::styled(
+            format!(" ({})", branch),
+            Style::default().fg(Color::LightMagenta),
+        ));
+    }
+
+    List::new(items).highlight_symbol(">> ").block(
+        Block::default()
+            .title(Spans::from(title))
+            .borders(Borders::ALL),
+    )
+}
+
+// two character git status column, blank outside of a repo or for clean entries
+fn git_status(status: Option<&Status>) -> Span<'static> {
+    let (text, color) = match status {
+        None => ("  ", Color::Reset),
+        Some(s) if s.conflicted => ("C ", Color::LightRed),
+        Some(s) if s.staged && s.modified => ("SM", Color::Yellow),
+        Some(s) if s.staged => ("S ", Color::LightGreen),
+        Some(s) if s.modified => ("M ", Color::Yellow),
+        Some(s) if s.untracked => ("? ", Color::LightMagenta),
+        Some(s) if s.ignored => ("! ", Color::DarkGray),
+        Some(_) => ("  ", Color::Reset),
+    };
+    Span::styled(text, Style::default().fg(color))
 }
 
+// built-in keybindings and their descriptions
+const KEYBINDINGS: [(&str, &str); 43] = [
+    ("q", "quit"),
+    ("j/k", "scroll"),
+    ("%", "new file"),
+    ("d", "new dir"),
+    ("backspace", "../"),
+    ("space", "preview"),
+    ("D", "delete"),
+    ("x", "trash"),
+    ("r", "rename"),
+    ("R", "bulk rename"),
+    ("M", "permissions"),
+    ("J", "jump to link target"),
+    ("L", "link marked here"),
+    ("c", "copy to"),
+    ("m", "move to"),
+    ("t", "Tmux"),
+    ("|", "Tmux split right"),
+    ("_", "Tmux split below"),
+    ("P", "Tmux popup"),
+    ("a", "send to Tmux pane"),
+    ("v", "mark"),
+    ("p", "dual pane"),
+    ("tab", "switch pane"),
+    ("n", "new tab"),
+    ("w", "close tab"),
+    ("[/]", "previous/next tab"),
+    ("1-9", "go to tab"),
+    ("s", "git stage"),
+    ("u", "git unstage"),
+    ("X", "git discard"),
+    ("=", "git diff"),
+    ("!", "shell"),
+    ("S", "shell (interactive)"),
+    (":", "run command"),
+    ("C", "choose (picker mode)"),
+    ("z", "dir sizes"),
+    ("U", "disk usage"),
+    ("E", "error log"),
+    ("i", "properties"),
+    ("f", "find"),
+    ("g", "grep"),
+    ("?", "help"),
+    ("esc", "close popup"),
+];
+
 pub fn quick_help() -> Paragraph<'static> {
-    let commands = vec!["q", "j/k", "%", "d", "backspace", "space", "D", "t"];
-    let titles = vec![
-        ":quit",
-        ":scroll",
-        ":new file",
-        ":new dir",
-        ":../",
-        ":preview",
-        ":delete",
-        ":Tmux",
-    ];
     let mut content = vec![];
 
-    for i in 0..commands.len() {
-        content.push(Span::styled(commands[i], Style::default().fg(Color::Cyan)));
-        content.push(Span::raw(titles[i].to_string()));
+    for (key, title) in KEYBINDINGS {
+        content.push(Span::styled(key, Style::default().fg(Color::Cyan)));
+        content.push(Span::raw(":".to_string() + title));
         content.push(Span::raw("  "));
     }
 
     let help_menu = Spans::from(content);
 
-    return Paragraph::new(help_menu)
-        .block(Block::default().title("Quick Help").borders(Borders::ALL));
+    Paragraph::new(help_menu).block(Block::default().title("Quick Help").borders(Borders::ALL))
+}
+
+// all built-in keybindings followed by the user commands and plugin actions
+// from kfm.toml
+pub fn help<'a>(
+    commands: &'a BTreeMap<String, UserCommand>,
+    plugins: &'a BTreeMap<String, Plugin>,
+) -> Paragraph<'a> {
+    let key_style = Style::default().fg(Color::Cyan);
+    let mut lines = vec![];
+
+    for (key, title) in KEYBINDINGS {
+        lines.push(Spans::from(vec![
+            Span::styled(format!("{:>10}", key), key_style),
+            Span::raw("  "),
+            Span::raw(title),
+        ]));
+    }
+
+    if !commands.is_empty() {
+        lines.push(Spans::from(""));
+        lines.push(Spans::from(Span::styled(
+            "User Commands",
+            Style::default().fg(Color::LightCyan),
+        )));
+        for (name, command) in commands {
+            let key = command.key.map(String::from).unwrap_or_default();
+            lines.push(Spans::from(vec![
+                Span::styled(format!("{:>10}", key), key_style),
+                Span::raw("  "),
+                Span::raw(name.as_str()),
+                Span::styled(
+                    format!("  {}", command.run),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]));
+        }
+    }
+
+    let actions: Vec<(&String, &Plugin)> =
+        plugins.iter().filter(|(_, p)| p.key.is_some()).collect();
+    if !actions.is_empty() {
+        lines.push(Spans::from(""));
+        lines.push(Spans::from(Span::styled(
+            "Plugins",
+            Style::default().fg(Color::LightCyan),
+        )));
+        for (name, plugin) in actions {
+            let key = plugin.key.map(String::from).unwrap_or_default();
+            lines.push(Spans::from(vec![
+                Span::styled(format!("{:>10}", key), key_style),
+                Span::raw("  "),
+                Span::raw(name.as_str()),
+                Span::styled(
+                    format!("  {}", plugin.exec),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]));
+        }
+    }
+
+    Paragraph::new(lines).block(Block::default().title(" Help ").borders(Borders::ALL))
+}
+
+pub fn input_field(input: &String, input_title: String) -> Paragraph<'_> {
+    Paragraph::new(String::from(input))
+        .block(Block::default().borders(Borders::ALL).title(input_title))
+}
+
+// one title per tab, numbered like the keys that switch to them
+pub fn tab_bar(titles: Vec<String>, active: usize) -> Tabs<'static> {
+    let titles = titles
+        .into_iter()
+        .enumerate()
+        .map(|(i, title)| Spans::from(format!("{}:{}", i + 1, title)))
+        .collect();
+    Tabs::new(titles)
+        .select(active)
+        .highlight_style(Style::default().fg(Color::Cyan))
+}
+
+// the latest message, shown for a few seconds below the listing
+pub fn status_line(message: String) -> Paragraph<'static> {
+    Paragraph::new(Span::styled(message, Style::default().fg(Color::LightRed)))
+}
+
+// details of the selection and the current dir
+pub fn status_bar(text: String) -> Paragraph<'static> {
+    Paragraph::new(Span::styled(text, Style::default().fg(Color::Gray)))
+}
+
+// the finder's matches, paths relative to where it started
+pub fn finder_list(matches: Vec<&str>) -> List<'_> {
+    let items: Vec<ListItem> = matches
+        .into_iter()
+        .map(|path| {
+            let style = if path.ends_with('/') {
+                Style::default().fg(Color::LightBlue)
+            } else {
+                Style::default()
+            };
+            ListItem::new(Span::styled(path, style))
+        })
+        .collect();
+    List::new(items)
+        .highlight_symbol(">> ")
+        .block(Block::default().borders(Borders::ALL))
+}
+
+// content search matches as path:line: text
+pub fn grep_list(matches: &[Match]) -> List<'_> {
+    let items: Vec<ListItem> = matches
+        .iter()
+        .map(|m| {
+            ListItem::new(Spans::from(vec![
+                Span::styled(m.path.as_str(), Style::default().fg(Color::LightMagenta)),
+                Span::raw(":"),
+                Span::styled(m.line.to_string(), Style::default().fg(Color::Green)),
+                Span::raw(": "),
+                Span::raw(m.text.as_str()),
+            ]))
+        })
+        .collect();
+    List::new(items)
+        .highlight_symbol(">> ")
+        .block(Block::default().borders(Borders::ALL))
+}
+
+// permission bits as toggles, the octal mode, owner and group, with the
+// focused field highlighted
+pub fn perms_editor(editor: &PermsEditor) -> Paragraph<'_> {
+    let focus = editor.field();
+    let style = |field: Field| {
+        if field == focus {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default()
+        }
+    };
+    let label = |text: &'static str| Span::styled(text, Style::default().fg(Color::Cyan));
+
+    let mut lines = vec![Spans::from(vec![Span::raw("          r   w   x")])];
+    for (row, name) in ["  user    ", "  group   ", "  other   "]
+        .into_iter()
+        .enumerate()
+    {
+        let mut spans = vec![label(name)];
+        for bit in 0..3 {
+            let i = row as u32 * 3 + bit;
+            let set = editor.mode & (0o400 >> i) != 0;
+            spans.push(Span::styled(
+                if set { "[x]" } else { "[ ]" },
+                style(Field::Bit(i)),
+            ));
+            spans.push(Span::raw(" "));
+        }
+        lines.push(Spans::from(spans));
+    }
+
+    lines.push(Spans::from(""));
+    for (name, field, value) in [
+        ("  mode    ", Field::Octal, &editor.octal),
+        ("  owner   ", Field::Owner, &editor.owner),
+        ("  group   ", Field::Group, &editor.group),
+    ] {
+        lines.push(Spans::from(vec![
+            label(name),
+            Span::styled(value.as_str(), style(field)),
+        ]));
+    }
+
+    if editor.has_dirs {
+        lines.push(Spans::from(""));
+        lines.push(Spans::from(vec![
+            label("  recurse "),
+            Span::styled(
+                if editor.recursive { "[x]" } else { "[ ]" },
+                style(Field::Recursive),
+            ),
+            Span::raw("  "),
+            Span::styled(editor.only.name(), style(Field::Only)),
+        ]));
+    }
+
+    lines.push(Spans::from(""));
+    lines.push(Spans::from(Span::styled(
+        "  tab: next  space: toggle  enter: apply  esc: cancel",
+        Style::default().fg(Color::DarkGray),
+    )));
+
+    let title = match editor.paths.as_slice() {
+        [path] => format!(" {} ", path.display()),
+        paths => format!(" {} entries ", paths.len()),
+    };
+    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title))
+}
+
+// the properties popup, a label column and a value column
+pub fn properties<'a>(rows: &'a [(&'static str, String)]) -> Paragraph<'a> {
+    let lines: Vec<Spans> = rows
+        .iter()
+        .map(|(label, value)| {
+            Spans::from(vec![
+                Span::styled(format!("{:>12}  ", label), Style::default().fg(Color::Cyan)),
+                Span::raw(value.as_str()),
+            ])
+        })
+        .collect();
+    Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::default().borders(Borders::ALL).title(" Properties "))
+}
+
+// every error reported this session, newest first
+pub fn error_log(errors: &[String]) -> Paragraph<'_> {
+    let lines: Vec<Spans> = if errors.is_empty() {
+        vec![Spans::from("No errors")]
+    } else {
+        errors
+            .iter()
+            .rev()
+            .map(|e| Spans::from(e.as_str()))
+            .collect()
+    };
+    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Errors "))
 }
 
-pub fn input_field(input: &String, input_title: String) -> Paragraph {
-    return Paragraph::new(String::from(input))
-        .block(Block::default().borders(Borders::ALL).title(input_title));
+// a one line prompt for picking one of a few keys
+pub fn choice_prompt(prompt: &str) -> Paragraph<'_> {
+    Paragraph::new(Span::styled(
+        prompt,
+        Style::default().fg(Color::LightYellow),
+    ))
+    .block(Block::default())
 }
 
-pub fn delete_prompt(prompt: &String) -> Paragraph {
+pub fn delete_prompt(prompt: &String) -> Paragraph<'_> {
     let styled_prompt = Spans::from(vec![
         Span::styled(prompt, Style::default().fg(Color::LightRed)),
         Span::raw(" [y/n]"),
     ]);
-    return Paragraph::new(styled_prompt).block(Block::default());
+    Paragraph::new(styled_prompt).block(Block::default())
 }
@@ -1,21 +1,182 @@
-use std::env;
+use std::{
+    env,
+    ffi::OsString,
+    io,
+    os::unix::ffi::OsStringExt,
+    path::{Component, Path, PathBuf},
+};
 
-pub fn get_working_dir() -> std::string::String {
-    if let Ok(current_dir) = env::current_dir() {
-        return current_dir.to_string_lossy().into_owned();
-    } else {
-        panic!("Failed to determine the current directory.")
+#[cfg(test)]
+use std::{fs, ops::Deref};
+
+// resolves . and .. without touching the filesystem, so it works for paths
+// under a dir that was removed
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                res.pop();
+            }
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+// the closest dir at or above `path` that still exists
+pub fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|a| a.is_dir())
+        .unwrap_or_else(|| Path::new("/"))
+        .to_path_buf()
+}
+
+// the file name in a listing entry, which in long mode is a line of `ls -l`
+// like "-rw-r--r-- 1 user group 42 Oct 19 08:31 name"
+pub fn entry_name(entry: &str) -> &str {
+    long_entry_name(entry).unwrap_or(entry)
+}
+
+fn long_entry_name(entry: &str) -> Option<&str> {
+    let mode = entry.get(..10)?;
+    let mut chars = mode.chars();
+    if !"-dlcbps".contains(chars.next()?) || !chars.all(|c| "rwxsStT-".contains(c)) {
+        return None;
+    }
+
+    // mode, links, owner, group, size and the three parts of the date, device
+    // files have "major, minor" in place of the size
+    let mut fields = 8;
+    let mut rest = entry;
+    let mut i = 0;
+    while i < fields {
+        rest = rest.trim_start_matches(' ');
+        let end = rest.find(' ')?;
+        let field = &rest[..end];
+        if i == 1 && !field.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        if i == 4 && field.ends_with(',') {
+            fields += 1;
+        }
+        rest = &rest[end..];
+        i += 1;
+    }
+
+    let name = rest.strip_prefix(' ')?;
+    // symlinks are followed by their target
+    match name.find(" -> ") {
+        Some(arrow) if mode.starts_with('l') => Some(&name[..arrow]),
+        _ => Some(name),
     }
 }
 
 pub fn is_dir(possible_dir: &str) -> bool {
     if let Some(last_char) = possible_dir.chars().last() {
-        if last_char == '/' {
-            return true;
-        } else {
-            return false;
+        return last_char == '/';
+    }
+
+    false
+}
+
+// wraps a string in single quotes so the shell treats it as one word
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// creates a new dir in $TMPDIR that only the user can get into, named
+// `prefix` followed by random characters
+pub fn temp_dir(prefix: &str) -> io::Result<PathBuf> {
+    let mut template = env::temp_dir()
+        .join(format!("{}XXXXXX", prefix))
+        .into_os_string()
+        .into_vec();
+    template.push(0);
+    // SAFETY: the template is nul terminated and mkdtemp only replaces the
+    // Xs in place
+    let dir = unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut libc::c_char) };
+    if dir.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    template.pop();
+    Ok(PathBuf::from(OsString::from_vec(template)))
+}
+
+// a temp dir for tests, removed with everything in it when dropped
+#[cfg(test)]
+pub struct TestDir(PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    pub fn new() -> TestDir {
+        TestDir(temp_dir("kfm-test-").unwrap())
+    }
+
+    // writes `contents` to `name` under the dir, making the dirs above it
+    pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
         }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+#[cfg(test)]
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
     }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    return false;
+    #[test]
+    fn normalize_resolves_dots() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize(Path::new("/../a")), Path::new("/a"));
+    }
+
+    #[test]
+    fn entry_names_in_long_mode() {
+        assert_eq!(entry_name("a b"), "a b");
+        assert_eq!(
+            entry_name("-rw-r--r--  1 user group   42 Oct 19 08:31 a  b"),
+            "a  b"
+        );
+        assert_eq!(
+            entry_name("drwxr-xr-x. 2 user group 4096 Jan  1  2020 dir/"),
+            "dir/"
+        );
+        assert_eq!(
+            entry_name("lrwxrwxrwx 1 user group 1 Oct 19 08:31 l -> a"),
+            "l"
+        );
+        assert_eq!(
+            entry_name("crw-rw-rw- 1 root root 1, 3 Oct 19 08:31 null"),
+            "null"
+        );
+        // only looks like a long entry
+        assert_eq!(entry_name("drwxr-xr-x x"), "drwxr-xr-x x");
+    }
+
+    #[test]
+    fn shell_quote_escapes_quotes() {
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
 }